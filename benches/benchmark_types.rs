@@ -3,9 +3,7 @@ use log::debug;
 use steel::{gen_code::Spec, handle, handle_steps, CompilerContext, SteelErr, Tasks};
 
 pub fn render_size(spec: &Spec) -> String {
-    spec.size
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| "".to_string())
+    spec.size.map(|s| s.to_string()).unwrap_or_default()
 }
 
 pub fn benchmark_parse<T: CompilerContext + Clone>(
@@ -35,7 +33,7 @@ pub fn benchmark_optimize<T: CompilerContext + Clone>(
     c.bench_function(&format!("{} optimize {}", name, bench_type), |b| {
         debug!("testing {} with {}\n{}", name, render_size(spec), program);
         let mut store = T::new();
-        let (id, _res) = handle_steps::<T>(&mut store, Tasks::parse(program))
+        let (id, _res, _output) = handle_steps::<T>(&mut store, Tasks::parse(program))
             .expect("Should parse program without error");
         let store = store;
         let id = id.expect("Should have parsed a program");
@@ -61,8 +59,9 @@ pub fn benchmark_eval_pre_optimized<T: CompilerContext + Clone>(
         |b| {
             debug!("testing {} with {}\n{}", name, render_size(spec), program);
             let mut store = T::new();
-            let (id, _res) = handle_steps::<T>(&mut store, Tasks::parse(program).and_optimize())
-                .expect("Should parse program without error");
+            let (id, _res, _output) =
+                handle_steps::<T>(&mut store, Tasks::parse(program).and_optimize())
+                    .expect("Should parse program without error");
             let store = store;
             let id = id.expect("Should have parsed a program");
             b.iter_batched_ref(
@@ -86,7 +85,7 @@ pub fn benchmark_eval<T: CompilerContext + Clone>(
     c.bench_function(&format!("{} eval {}", name, bench_type), |b| {
         debug!("testing {} with {}\n{}", name, render_size(spec), program);
         let mut store = T::new();
-        let (id, _res) = handle_steps::<T>(&mut store, Tasks::parse(program))
+        let (id, _res, _output) = handle_steps::<T>(&mut store, Tasks::parse(program))
             .expect("Should parse program without error");
         let store = store;
         let id = id.expect("Should have parsed a program");
@@ -1,7 +1,7 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use steel::{
     ast, ecs,
-    gen_code::{generate_random_program, Spec},
+    gen_code::{generate_random_program, seed_from_env, Spec},
     CompilerContext,
 };
 
@@ -10,7 +10,10 @@ use benchmark_types::*;
 
 fn criterion_benchmark(c: &mut Criterion) {
     let _ = env_logger::builder().is_test(true).try_init();
-    let mut rng = rand::thread_rng();
+    // One seed for all the sizes, so that a run can be replayed with `STEEL_SEED`.
+    let seed = seed_from_env();
+    eprintln!("random programs from STEEL_SEED={}", seed);
+    let mut rng = Spec::default().seeded(seed).rng();
     for i in 0..4 {
         let size: usize = 10usize.pow(i);
         let spec = Spec::default().sized(size);
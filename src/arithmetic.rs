@@ -0,0 +1,101 @@
+use crate::nodes::Operator;
+
+// How integer arithmetic handles results that don't fit in an i64.
+// Both the interpreter and constant folding use this, so that optimizing doesn't change results.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum Arithmetic {
+    #[default]
+    Wrapping, // e.g. `i64::MAX+1` is `i64::MIN`.
+    Checked,    // e.g. `i64::MAX+1` is an error.
+    Saturating, // e.g. `i64::MAX+1` is `i64::MAX`.
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ArithmeticError {
+    Overflow(Operator),
+    DivisionByZero,
+    NotArithmetic(Operator),
+}
+
+impl std::fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArithmeticError::Overflow(op) => write!(f, "Overflow in {}", op),
+            ArithmeticError::DivisionByZero => write!(f, "Division by zero"),
+            ArithmeticError::NotArithmetic(op) => write!(f, "{} is not an arithmetic operator", op),
+        }
+    }
+}
+
+impl Arithmetic {
+    // Division by zero has no wrapped or saturated result, so it is an error in every mode.
+    pub fn binary(self, op: Operator, l: i64, r: i64) -> Result<i64, ArithmeticError> {
+        use Arithmetic::*;
+        use Operator::*;
+        if op == Div && r == 0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        let result = match (self, op) {
+            (Wrapping, Add) => Some(l.wrapping_add(r)),
+            (Wrapping, Sub) => Some(l.wrapping_sub(r)),
+            (Wrapping, Mul) => Some(l.wrapping_mul(r)),
+            (Wrapping, Div) => Some(l.wrapping_div(r)),
+            (Checked, Add) => l.checked_add(r),
+            (Checked, Sub) => l.checked_sub(r),
+            (Checked, Mul) => l.checked_mul(r),
+            (Checked, Div) => l.checked_div(r),
+            (Saturating, Add) => Some(l.saturating_add(r)),
+            (Saturating, Sub) => Some(l.saturating_sub(r)),
+            (Saturating, Mul) => Some(l.saturating_mul(r)),
+            (Saturating, Div) => Some(l.saturating_div(r)),
+            (_, op) => return Err(ArithmeticError::NotArithmetic(op)),
+        };
+        result.ok_or(ArithmeticError::Overflow(op))
+    }
+
+    pub fn negate(self, v: i64) -> Result<i64, ArithmeticError> {
+        match self {
+            Arithmetic::Wrapping => Ok(v.wrapping_neg()),
+            Arithmetic::Checked => v
+                .checked_neg()
+                .ok_or(ArithmeticError::Overflow(Operator::Neg)),
+            Arithmetic::Saturating => Ok(v.saturating_neg()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Arithmetic::*;
+    use Operator::*;
+
+    #[test]
+    fn handles_overflow_by_mode() {
+        assert_eq!(Wrapping.binary(Add, i64::MAX, 1), Ok(i64::MIN));
+        assert_eq!(Saturating.binary(Add, i64::MAX, 1), Ok(i64::MAX));
+        assert_eq!(
+            Checked.binary(Add, i64::MAX, 1),
+            Err(ArithmeticError::Overflow(Add))
+        );
+        assert_eq!(Wrapping.binary(Div, i64::MIN, -1), Ok(i64::MIN));
+        assert_eq!(Saturating.binary(Div, i64::MIN, -1), Ok(i64::MAX));
+        assert_eq!(
+            Checked.binary(Div, i64::MIN, -1),
+            Err(ArithmeticError::Overflow(Div))
+        );
+        assert_eq!(Saturating.negate(i64::MIN), Ok(i64::MAX));
+        assert_eq!(
+            Checked.negate(i64::MIN),
+            Err(ArithmeticError::Overflow(Neg))
+        );
+        for mode in [Wrapping, Checked, Saturating] {
+            assert_eq!(mode.binary(Mul, -6, 7), Ok(-42));
+            assert_eq!(mode.binary(Div, 1, 0), Err(ArithmeticError::DivisionByZero));
+            assert_eq!(
+                mode.binary(Lt, 1, 2),
+                Err(ArithmeticError::NotArithmetic(Lt))
+            );
+        }
+    }
+}
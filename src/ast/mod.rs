@@ -1,6 +1,6 @@
-use crate::tombstoning_arena::{Arena, ArenaError, Index};
 use crate::compiler_context::{CompilerContext, NodeStore};
 use crate::nodes::*;
+use crate::tombstoning_arena::{Arena, ArenaError, Index};
 
 mod node;
 use node::*;
@@ -8,6 +8,8 @@ use node::*;
 #[derive(Debug)]
 pub enum AstError {
     NodeOfWrongKindError(Index, &'static str),
+    SpanNotFound(Index),
+    TriviaNotFound(Index),
     InternalError(ArenaError),
 }
 
@@ -22,6 +24,8 @@ use AstError::*;
 #[derive(Clone, Debug, Default)]
 pub struct Ast {
     members: Arena<Node>,
+    spans: Vec<Option<Span>>,    // Side table, indexed by node.
+    trivia: Vec<Option<Trivia>>, // Side table, indexed by node.
 }
 
 impl Ast {
@@ -33,9 +37,16 @@ impl Ast {
 impl CompilerContext for Ast
 where
     Self: NodeStore<Index, i64, AstError>,
+    Self: NodeStore<Index, f64, AstError>,
+    Self: NodeStore<Index, bool, AstError>,
+    Self: NodeStore<Index, StrLiteral, AstError>,
     Self: NodeStore<Index, Operator, AstError>,
     Self: NodeStore<Index, Symbol, AstError>,
     Self: NodeStore<Index, Call<Index>, AstError>,
+    Self: NodeStore<Index, Invalid, AstError>,
+    Self: NodeStore<Index, Let<Index>, AstError>,
+    Self: NodeStore<Index, Lambda<Index>, AstError>,
+    Self: NodeStore<Index, If<Index>, AstError>,
 {
     type ID = Index;
     type E = AstError;
@@ -44,12 +55,52 @@ where
         Self::new()
     }
 
+    fn set_span(&mut self, id: Self::ID, span: Span) -> Result<(), Self::E> {
+        self.members.get(id)?; // Only attach spans to real nodes.
+        if self.spans.len() <= id {
+            self.spans.resize(id + 1, None);
+        }
+        self.spans[id] = Some(span);
+        Ok(())
+    }
+
+    fn get_span(&self, id: Self::ID) -> Result<&Span, Self::E> {
+        if let Some(Some(span)) = self.spans.get(id) {
+            Ok(span)
+        } else {
+            Err(SpanNotFound(id))
+        }
+    }
+
+    fn set_trivia(&mut self, id: Self::ID, trivia: Trivia) -> Result<(), Self::E> {
+        self.members.get(id)?; // Only attach trivia to real nodes.
+        if self.trivia.len() <= id {
+            self.trivia.resize(id + 1, None);
+        }
+        self.trivia[id] = Some(trivia);
+        Ok(())
+    }
+
+    fn get_trivia(&self, id: Self::ID) -> Result<&Trivia, Self::E> {
+        if let Some(Some(trivia)) = self.trivia.get(id) {
+            Ok(trivia)
+        } else {
+            Err(TriviaNotFound(id))
+        }
+    }
+
     fn active_mem_usage(&self) -> usize {
-        std::mem::size_of::<Self>() + self.members.active_mem_usage()
+        std::mem::size_of::<Self>()
+            + self.members.active_mem_usage()
+            + self.spans.len() * std::mem::size_of::<Option<Span>>()
+            + self.trivia.len() * std::mem::size_of::<Option<Trivia>>()
     }
 
     fn mem_usage(&self) -> usize {
-        std::mem::size_of::<Self>() + self.members.mem_usage()
+        std::mem::size_of::<Self>()
+            + self.members.mem_usage()
+            + self.spans.capacity() * std::mem::size_of::<Option<Span>>()
+            + self.trivia.capacity() * std::mem::size_of::<Option<Trivia>>()
     }
 
     fn for_each<
@@ -97,6 +148,13 @@ where
                         i64_fn(self, index, value)
                     }
                 }
+                Node::F64(_)
+                | Node::Bool(_)
+                | Node::Str(_)
+                | Node::Invalid(_)
+                | Node::Let(_)
+                | Node::Lambda(_)
+                | Node::If(_) => {}
             }
             {
                 // swap to get the real value back in place
@@ -179,9 +237,16 @@ macro_rules! wrap_node {
 }
 
 wrap_node!(i64, I64);
+wrap_node!(f64, F64);
+wrap_node!(bool, Bool);
+wrap_node!(StrLiteral, Str);
 wrap_node!(Operator, Operator);
 wrap_node!(Symbol, Symbol);
 wrap_node!(Call<Index>, Call);
+wrap_node!(Invalid, Invalid);
+wrap_node!(Let<Index>, Let);
+wrap_node!(Lambda<Index>, Lambda);
+wrap_node!(If<Index>, If);
 
 #[cfg(test)]
 mod test {
@@ -228,6 +293,56 @@ mod test {
         );
     }
 
+    #[test]
+    fn spans_survive_replace() -> Result<(), AstError> {
+        let mut ctx: Ast = Ast::new();
+
+        let hello = ctx.add(Symbol::new("hello"));
+        ctx.set_span(hello, Span::new(3, 8))?;
+        ctx.replace(hello, 12i64)?;
+
+        assert_eq!(ctx.get_i64(hello)?, &12);
+        assert_eq!(ctx.get_span(hello)?, &Span::new(3, 8));
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_replace_missing_node() {
+        let mut other: Ast = Ast::new();
+        let hello = other.add(Symbol::new("hello"));
+        let mut ctx: Ast = Ast::new();
+
+        assert!(ctx.replace(hello, 12i64).is_err());
+    }
+
+    #[test]
+    fn trivia_survives_replace() -> Result<(), AstError> {
+        let mut ctx: Ast = Ast::new();
+
+        let hello = ctx.add(Symbol::new("hello"));
+        let trivia = Trivia {
+            leading: vec!["// hi".to_string()],
+            trailing: vec![],
+        };
+        ctx.set_trivia(hello, trivia.clone())?;
+        ctx.replace(hello, 12i64)?;
+
+        assert_eq!(ctx.get_trivia(hello)?, &trivia);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_spans_are_errors() {
+        let mut ctx: Ast = Ast::new();
+
+        let hello = ctx.add(Symbol::new("hello"));
+
+        assert_eq!(
+            format!("{:?}", ctx.get_span(hello)),
+            format!("Err(SpanNotFound({:?}))", hello)
+        );
+    }
+
     /*
     #[test]
     fn can_construct_nodes_with_self_reference() {
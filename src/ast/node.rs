@@ -1,12 +1,19 @@
-use crate::tombstoning_arena::Index;
 use crate::nodes::*;
+use crate::tombstoning_arena::Index;
 
 #[derive(Clone, Debug)]
 pub enum Node {
     I64(i64),
+    F64(f64),
+    Bool(bool),
+    Str(StrLiteral),
     Operator(Operator),
     Symbol(Symbol),
     Call(Call<Index>),
+    Invalid(Invalid),
+    Let(Let<Index>),
+    Lambda(Lambda<Index>),
+    If(If<Index>),
 }
 
 #[cfg(test)]
@@ -0,0 +1,6 @@
+use steel::ast::Ast;
+use steel::debugger::run;
+
+fn main() {
+    run::<Ast>();
+}
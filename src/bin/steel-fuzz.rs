@@ -0,0 +1,5 @@
+use steel::fuzz::run;
+
+fn main() {
+    run();
+}
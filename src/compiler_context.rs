@@ -1,4 +1,4 @@
-use crate::nodes::{Call, Operator, Symbol};
+use crate::nodes::{Call, If, Invalid, Lambda, Let, Operator, Span, StrLiteral, Symbol, Trivia};
 
 pub trait NodeStore<ID, T, E> {
     fn add(&mut self, value: T) -> ID;
@@ -22,9 +22,16 @@ pub trait CompilerContext:
     + NodeStore<Self::ID, Symbol, Self::E>
     + NodeStore<Self::ID, Operator, Self::E>
     + NodeStore<Self::ID, i64, Self::E>
+    + NodeStore<Self::ID, f64, Self::E>
+    + NodeStore<Self::ID, bool, Self::E>
+    + NodeStore<Self::ID, StrLiteral, Self::E>
+    + NodeStore<Self::ID, Invalid, Self::E>
+    + NodeStore<Self::ID, Let<Self::ID>, Self::E>
+    + NodeStore<Self::ID, Lambda<Self::ID>, Self::E>
+    + NodeStore<Self::ID, If<Self::ID>, Self::E>
     + std::fmt::Debug
 {
-    type ID: Eq + std::hash::Hash + Copy + std::fmt::Debug;
+    type ID: Eq + std::hash::Hash + Copy + std::fmt::Debug + 'static;
     type E: Into<crate::error::SteelErr> + std::fmt::Debug;
 
     fn new() -> Self;
@@ -52,6 +59,70 @@ pub trait CompilerContext:
     fn get_i64_mut(&mut self, id: Self::ID) -> Result<&mut i64, Self::E> {
         self.get_mut(id)
     }
+    fn get_f64(&self, id: Self::ID) -> Result<&f64, Self::E> {
+        self.get(id)
+    }
+    fn get_f64_mut(&mut self, id: Self::ID) -> Result<&mut f64, Self::E> {
+        self.get_mut(id)
+    }
+    fn get_bool(&self, id: Self::ID) -> Result<&bool, Self::E> {
+        self.get(id)
+    }
+    fn get_bool_mut(&mut self, id: Self::ID) -> Result<&mut bool, Self::E> {
+        self.get_mut(id)
+    }
+    fn get_str(&self, id: Self::ID) -> Result<&StrLiteral, Self::E> {
+        self.get(id)
+    }
+    fn get_str_mut(&mut self, id: Self::ID) -> Result<&mut StrLiteral, Self::E> {
+        self.get_mut(id)
+    }
+    fn get_invalid(&self, id: Self::ID) -> Result<&Invalid, Self::E> {
+        self.get(id)
+    }
+    fn get_let(&self, id: Self::ID) -> Result<&Let<Self::ID>, Self::E> {
+        self.get(id)
+    }
+    fn get_let_mut(&mut self, id: Self::ID) -> Result<&mut Let<Self::ID>, Self::E> {
+        self.get_mut(id)
+    }
+    fn get_lambda(&self, id: Self::ID) -> Result<&Lambda<Self::ID>, Self::E> {
+        self.get(id)
+    }
+    fn get_lambda_mut(&mut self, id: Self::ID) -> Result<&mut Lambda<Self::ID>, Self::E> {
+        self.get_mut(id)
+    }
+    fn get_if(&self, id: Self::ID) -> Result<&If<Self::ID>, Self::E> {
+        self.get(id)
+    }
+    fn get_if_mut(&mut self, id: Self::ID) -> Result<&mut If<Self::ID>, Self::E> {
+        self.get_mut(id)
+    }
+    // Spans are attached to existing nodes (and survive `replace`) rather than being nodes.
+    fn set_span(&mut self, id: Self::ID, span: Span) -> Result<(), Self::E>;
+    fn get_span(&self, id: Self::ID) -> Result<&Span, Self::E>;
+    // Likewise for comments (which are only kept when requested).
+    fn set_trivia(&mut self, id: Self::ID, trivia: Trivia) -> Result<(), Self::E>;
+    fn get_trivia(&self, id: Self::ID) -> Result<&Trivia, Self::E>;
+    // Visit every node reachable from `id` (parents before children).
+    fn walk<F: FnMut(&Self, Self::ID)>(&self, id: Self::ID, f: &mut F) {
+        f(self, id);
+        if let Ok(call) = self.get_call(id) {
+            self.walk(call.callee, f);
+            for (_arg_name, arg) in &call.args {
+                self.walk(*arg, f);
+            }
+        } else if let Ok(binding) = self.get_let(id) {
+            self.walk(binding.value, f);
+            self.walk(binding.body, f);
+        } else if let Ok(lambda) = self.get_lambda(id) {
+            self.walk(lambda.body, f);
+        } else if let Ok(conditional) = self.get_if(id) {
+            self.walk(conditional.condition, f);
+            self.walk(conditional.then, f);
+            self.walk(conditional.otherwise, f);
+        }
+    }
     fn replace<T>(&mut self, id: Self::ID, value: T) -> Result<(), Self::E>
     where
         Self: NodeStore<Self::ID, T, Self::E>,
@@ -62,9 +133,16 @@ pub trait CompilerContext:
         <Self as NodeStore<Self::ID, Symbol, Self::E>>::remove_any(self, id);
         <Self as NodeStore<Self::ID, Operator, Self::E>>::remove_any(self, id);
         <Self as NodeStore<Self::ID, i64, Self::E>>::remove_any(self, id);
+        <Self as NodeStore<Self::ID, f64, Self::E>>::remove_any(self, id);
+        <Self as NodeStore<Self::ID, bool, Self::E>>::remove_any(self, id);
+        <Self as NodeStore<Self::ID, StrLiteral, Self::E>>::remove_any(self, id);
+        <Self as NodeStore<Self::ID, Invalid, Self::E>>::remove_any(self, id);
+        <Self as NodeStore<Self::ID, Let<Self::ID>, Self::E>>::remove_any(self, id);
+        <Self as NodeStore<Self::ID, Lambda<Self::ID>, Self::E>>::remove_any(self, id);
+        <Self as NodeStore<Self::ID, If<Self::ID>, Self::E>>::remove_any(self, id);
 
         // TODO: Construct new, don't just get_mut...
-        <Self as NodeStore<Self::ID, T, Self::E>>::overwrite(self, id, value).expect("FAILED!?");
+        <Self as NodeStore<Self::ID, T, Self::E>>::overwrite(self, id, value)?;
         Ok(())
     }
 
@@ -145,8 +223,11 @@ pub trait CompilerContext:
     fn active_mem_usage(&self) -> usize;
     fn mem_usage(&self) -> usize;
     fn pretty(&self, id: Self::ID) -> String {
+        self.pretty_with(id, &crate::operators::OperatorTable::default())
+    }
+    fn pretty_with(&self, id: Self::ID, operators: &crate::operators::OperatorTable) -> String {
         use crate::pretty_printer::pretty;
-        pretty(self, id)
+        pretty(self, id, operators)
     }
     fn optimize(
         &mut self,
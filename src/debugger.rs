@@ -0,0 +1,425 @@
+use crate::compiler_context::CompilerContext;
+use crate::diagnostics;
+use crate::error::SteelErr;
+use crate::interpreter::{Environment, Evaluator};
+use crate::io::StdIo;
+use crate::nodes::Span;
+use std::io::Write;
+
+const HELP: &str = "\
+commands:
+  s, step              run until the next node (stepping into calls)
+  n, next              run until the next node after this one (stepping over calls)
+  o, out               run until the current call returns
+  c, continue          run until a breakpoint or the end of the program
+  b, break START..END  stop at nodes with this span
+  b, break #ID         stop at the node with this id
+  d, delete N          remove the Nth breakpoint
+  l, list              show the breakpoints
+  bt, backtrace        show the function stack (the next frame to run first)
+  p, print [NAME]      show the bindings in scope (or just NAME)
+  m, memory            show the memory used by the program
+  h, help              show this message
+  q, quit              stop debugging
+";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    Span(Span),   // stop at nodes with exactly this span.
+    Node(String), // stop at the node with this id (as shown in locations and backtraces).
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Breakpoint::Span(span) => write!(f, "{}", span),
+            Breakpoint::Node(id) => write!(f, "#{}", id),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Step,
+    Next,
+    Out,
+    Continue,
+    Break(Breakpoint),
+    Delete(usize),
+    List,
+    Backtrace,
+    Print(Option<String>),
+    Memory,
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("step");
+        let arg = words.next();
+        if let Some(extra) = words.next() {
+            return Err(format!("unexpected argument {:?}", extra));
+        }
+        let command = match (command, arg) {
+            ("s" | "step", None) => Command::Step,
+            ("n" | "next", None) => Command::Next,
+            ("o" | "out", None) => Command::Out,
+            ("c" | "continue", None) => Command::Continue,
+            ("b" | "break", Some(target)) => Command::Break(parse_breakpoint(target)?),
+            ("d" | "delete", Some(n)) => Command::Delete(
+                n.parse()
+                    .map_err(|_| format!("expected a breakpoint number, found {:?}", n))?,
+            ),
+            ("l" | "list", None) => Command::List,
+            ("bt" | "backtrace", None) => Command::Backtrace,
+            ("p" | "print", name) => Command::Print(name.map(str::to_string)),
+            ("m" | "memory", None) => Command::Memory,
+            ("h" | "help", None) => Command::Help,
+            ("q" | "quit", None) => Command::Quit,
+            _ => return Err(format!("unknown command {:?} (try \"help\")", line.trim())),
+        };
+        Ok(command)
+    }
+}
+
+fn parse_breakpoint(target: &str) -> Result<Breakpoint, String> {
+    if let Some(id) = target.strip_prefix('#') {
+        return Ok(Breakpoint::Node(id.to_string()));
+    }
+    let span = target.split_once("..").and_then(|(start, end)| {
+        Some(Span {
+            start: start.parse().ok()?,
+            end: end.parse().ok()?,
+        })
+    });
+    span.map(Breakpoint::Span)
+        .ok_or_else(|| format!("expected START..END or #ID, found {:?}", target))
+}
+
+// Drives an `Evaluator` from debugger commands, describing what happened as text.
+pub struct Debugger<'a, C: CompilerContext> {
+    context: &'a C,
+    evaluator: Evaluator<'a, C>,
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl<'a, C: CompilerContext> Debugger<'a, C>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    pub fn new(context: &'a C, expr: C::ID) -> Self {
+        Self::with_environment(context, expr, Environment::default())
+    }
+
+    pub fn with_environment(context: &'a C, expr: C::ID, environment: Environment<C::ID>) -> Self {
+        Self {
+            context,
+            evaluator: Evaluator::with_environment(context, expr, environment),
+            breakpoints: Vec::new(),
+        }
+    }
+
+    pub fn evaluator(&self) -> &Evaluator<'a, C> {
+        &self.evaluator
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn command(&mut self, command: Command) -> Result<String, SteelErr> {
+        let output = match command {
+            Command::Step => self.resume(|_evaluator| true)?,
+            Command::Next => {
+                let depth = self.evaluator.state().function_stack.len();
+                self.resume(|evaluator| evaluator.state().function_stack.len() < depth)?
+            }
+            Command::Out => {
+                let depth = self.evaluator.call_depth();
+                self.resume(|evaluator| evaluator.call_depth() < depth)?
+            }
+            Command::Continue => self.resume(|_evaluator| false)?,
+            Command::Break(breakpoint) => {
+                let added = format!("breakpoint {} at {}", self.breakpoints.len(), breakpoint);
+                self.breakpoints.push(breakpoint);
+                added
+            }
+            Command::Delete(n) if n < self.breakpoints.len() => {
+                format!("deleted breakpoint at {}", self.breakpoints.remove(n))
+            }
+            Command::Delete(n) => format!("there is no breakpoint {}", n),
+            Command::List => self.list(),
+            Command::Backtrace => self.backtrace(),
+            Command::Print(name) => self.print(name.as_deref()),
+            Command::Memory => self.memory(),
+            Command::Help => HELP.trim_end().to_string(),
+            Command::Quit => String::new(),
+        };
+        Ok(output)
+    }
+
+    // Run at least one frame, then stop at the first node where `stop` holds or a breakpoint is hit.
+    fn resume<P: FnMut(&Evaluator<'a, C>) -> bool>(
+        &mut self,
+        mut stop: P,
+    ) -> Result<String, SteelErr> {
+        if self.evaluator.step()? {
+            let context = self.context;
+            let breakpoints = &self.breakpoints;
+            self.evaluator
+                .run_until(|evaluator| match evaluator.current_node() {
+                    Some(id) => stop(evaluator) || hits_breakpoint(context, breakpoints, id),
+                    None => false,
+                })?;
+        }
+        Ok(self.location())
+    }
+
+    // Where evaluation has stopped.
+    pub fn location(&self) -> String {
+        match self.evaluator.current_node() {
+            Some(id) => format!("{} {}", self.describe(id), self.context.pretty(id)),
+            None => match self.evaluator.result() {
+                Some(value) => format!("finished with {:?}", value),
+                None => "finished without a result".to_string(),
+            },
+        }
+    }
+
+    fn describe(&self, id: C::ID) -> String {
+        match self.context.get_span(id) {
+            Ok(span) => format!("#{:?} at {}:", id, span),
+            Err(_) => format!("#{:?}:", id),
+        }
+    }
+
+    fn list(&self) -> String {
+        let lines: Vec<String> = self
+            .breakpoints
+            .iter()
+            .enumerate()
+            .map(|(n, breakpoint)| format!("{}: {}", n, breakpoint))
+            .collect();
+        if lines.is_empty() {
+            return "no breakpoints".to_string();
+        }
+        lines.join("\n")
+    }
+
+    pub fn backtrace(&self) -> String {
+        let snapshot = self.evaluator.snapshot();
+        let lines: Vec<String> = snapshot
+            .frames
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(n, frame)| match frame.node {
+                Some(id) => format!("{:>3}: {} {}", n, self.describe(id), frame.description),
+                None => format!("{:>3}: {}", n, frame.description),
+            })
+            .collect();
+        if lines.is_empty() {
+            return "the function stack is empty".to_string();
+        }
+        lines.join("\n")
+    }
+
+    pub fn print(&self, name: Option<&str>) -> String {
+        let snapshot = self.evaluator.snapshot();
+        match name {
+            Some(name) => match snapshot.bindings.get(name) {
+                Some(value) => format!("{} = {:?}", name, value),
+                None => format!("{} is not bound", name),
+            },
+            None if snapshot.bindings.is_empty() => "no bindings".to_string(),
+            None => {
+                let lines: Vec<String> = snapshot
+                    .bindings
+                    .iter()
+                    .map(|(name, value)| format!("{} = {:?}", name, value))
+                    .collect();
+                lines.join("\n")
+            }
+        }
+    }
+
+    // The program's memory (skipping the externs, which are allocated first).
+    pub fn memory(&self) -> String {
+        let snapshot = self.evaluator.snapshot();
+        let start = self.evaluator.result_index().id;
+        let lines: Vec<String> = snapshot
+            .memory
+            .iter()
+            .enumerate()
+            .skip(start)
+            .map(|(index, value)| format!("{:>4}: {:?}", index, value))
+            .collect();
+        lines.join("\n")
+    }
+}
+
+fn hits_breakpoint<C: CompilerContext>(context: &C, breakpoints: &[Breakpoint], id: C::ID) -> bool {
+    breakpoints.iter().any(|breakpoint| match breakpoint {
+        Breakpoint::Span(span) => context.get_span(id).is_ok_and(|found| found == span),
+        Breakpoint::Node(node) => *node == format!("{:?}", id),
+    })
+}
+
+// Debug the program in the file named by the first argument, reading commands from stdin.
+pub fn run<Ctx: CompilerContext>()
+where
+    SteelErr: From<<Ctx as CompilerContext>::E>,
+{
+    env_logger::init();
+    let mut args = std::env::args();
+    let _program_path = args.next();
+    let path = match (args.next(), args.next()) {
+        (Some(path), None) => path,
+        _ => {
+            eprintln!("usage: steel-debug PROGRAM");
+            std::process::exit(1);
+        }
+    };
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
+    let style = diagnostics::Style::for_stderr();
+    let mut store = Ctx::new();
+    let expr = match crate::parser::program(&mut store, &source) {
+        Ok((_rest, expr)) => expr,
+        Err(err) => {
+            let err: SteelErr = err.into();
+            eprint!("{}", diagnostics::render(&err, &source, &path, style));
+            std::process::exit(1);
+        }
+    };
+    // The program's output is shown as it is written.
+    let environment = Environment::default().with_io(StdIo::default());
+    let mut debugger = Debugger::with_environment(&store, expr, environment);
+    println!("{}", debugger.location());
+    loop {
+        print!("(steel) ");
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let command = match Command::parse(&line) {
+            Ok(Command::Quit) => return,
+            Ok(command) => command,
+            Err(message) => {
+                println!("{}", message);
+                continue;
+            }
+        };
+        match debugger.command(command) {
+            Ok(output) => println!("{}", output),
+            Err(err) => eprint!("{}", diagnostics::render(&err, &source, &path, style)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ast::Ast;
+    use crate::ecs::Ecs;
+
+    const PROGRAM: &str = "let double = (x) => x*2; double(3)+1";
+
+    fn debug<Ctx: CompilerContext>(commands: &[&str]) -> Result<Vec<String>, SteelErr>
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        let mut store = Ctx::new();
+        let (_, expr) = crate::parser::program(&mut store, PROGRAM)?;
+        let mut debugger = Debugger::new(&store, expr);
+        let mut outputs = vec![debugger.location()];
+        for line in commands {
+            let command = Command::parse(line).expect("valid command");
+            outputs.push(debugger.command(command)?);
+        }
+        Ok(outputs)
+    }
+
+    fn last_lines<Ctx: CompilerContext>(commands: &[&str]) -> Vec<String>
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        let outputs = debug::<Ctx>(commands).unwrap();
+        outputs
+            .iter()
+            .map(|output| output.split(": ").last().unwrap_or("").to_string())
+            .collect()
+    }
+
+    fn steps_through_calls<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        assert_eq!(
+            last_lines::<Ctx>(&["step", "next", "step", "step"])[1..],
+            ["(x) => x*2", "double(3)+1", "double(3)", "3"]
+        );
+        // Stepping into a call reaches its body, stepping out returns to the caller.
+        let lines = last_lines::<Ctx>(&["b 25..34", "c", "s", "s", "s", "s", "s", "o", "c"]);
+        assert_eq!(lines[2..6], ["double(3)", "3", "double", "x*2"]);
+        assert_eq!(lines[7], "2");
+        assert_eq!(lines[8], "1", "{:?}", lines);
+        assert_eq!(lines[9], "finished with 7_i64");
+    }
+
+    #[test]
+    fn steps_through_calls_ast() {
+        steps_through_calls::<Ast>();
+    }
+
+    #[test]
+    fn steps_through_calls_ecs() {
+        steps_through_calls::<Ecs>();
+    }
+
+    #[test]
+    fn shows_bindings_and_backtraces() {
+        let outputs = debug::<Ast>(&["b 20..23", "c", "p", "p x", "p y", "bt", "m"]).unwrap();
+        assert_eq!(outputs[2].split(": ").last(), Some("x*2"));
+        // Only the closure's own bindings are in scope in its body.
+        assert!(outputs[3].contains("self = func#"), "{}", outputs[3]);
+        assert!(!outputs[3].contains("double"), "{}", outputs[3]);
+        assert!(outputs[3].contains("x = 3_i64"), "{}", outputs[3]);
+        assert_eq!(outputs[4], "x = 3_i64");
+        assert_eq!(outputs[5], "y is not bound");
+        let backtrace: Vec<&str> = outputs[6].lines().collect();
+        assert!(backtrace[0].ends_with(": x*2"), "{}", outputs[6]);
+        assert!(backtrace.iter().any(|line| line.ends_with("unbind double")));
+        assert!(outputs[7].contains("3_i64"), "{}", outputs[7]);
+    }
+
+    #[test]
+    fn parses_commands() {
+        let span = Span { start: 1, end: 4 };
+        assert_eq!(
+            Command::parse("b 1..4"),
+            Ok(Command::Break(Breakpoint::Span(span)))
+        );
+        assert_eq!(
+            Command::parse("break #12"),
+            Ok(Command::Break(Breakpoint::Node("12".to_string())))
+        );
+        assert_eq!(Command::parse(""), Ok(Command::Step));
+        assert_eq!(
+            Command::parse("p x"),
+            Ok(Command::Print(Some("x".to_string())))
+        );
+        assert!(Command::parse("b 1-4").is_err());
+        assert!(Command::parse("frobnicate").is_err());
+        assert!(Command::parse("step 2").is_err());
+    }
+}
@@ -0,0 +1,331 @@
+use crate::error::SteelErr;
+use crate::nodes::Span;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    Ansi,
+}
+
+impl Style {
+    // Colour diagnostics only when a person is reading them.
+    pub fn for_stderr() -> Self {
+        use std::io::IsTerminal;
+        if std::io::stderr().is_terminal() {
+            Style::Ansi
+        } else {
+            Style::Plain
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const ERROR: &str = "\x1b[1;31m";
+const GUTTER: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(err: &SteelErr, source: &str) -> Self {
+        let mut diagnostic = Self {
+            code: err.code(),
+            message: message(err),
+            span: locate(err, source),
+            notes: Vec::new(),
+            help: Vec::new(),
+        };
+        explain(&mut diagnostic, err);
+        diagnostic
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> Self {
+        self.help.push(help.to_string());
+        self
+    }
+
+    pub fn render(&self, source: &str, origin: &str, style: Style) -> String {
+        let paint = |colour: &str, text: &str| match style {
+            Style::Plain => text.to_string(),
+            Style::Ansi => format!("{}{}{}", colour, text, RESET),
+        };
+        let mut out = format!(
+            "{}{}\n",
+            paint(ERROR, &format!("error[{}]", self.code)),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+        let span = if let Some(span) = self.span {
+            span
+        } else {
+            for note in &self.notes {
+                out += &format!(
+                    "  {} {} {}\n",
+                    paint(GUTTER, "="),
+                    paint(BOLD, "note:"),
+                    note
+                );
+            }
+            for help in &self.help {
+                out += &format!(
+                    "  {} {} {}\n",
+                    paint(GUTTER, "="),
+                    paint(BOLD, "help:"),
+                    help
+                );
+            }
+            return out;
+        };
+        let (line, col) = span.line_col(source);
+        let line_txt = source.split('\n').nth(line - 1).unwrap_or("");
+        let line_num = line.to_string();
+        let pad = " ".repeat(line_num.len());
+        // Underline up to the end of the first line of the span.
+        let width = span
+            .text(source)
+            .split('\n')
+            .next()
+            .map(|txt| txt.chars().count())
+            .unwrap_or(0)
+            .max(1);
+        out += &format!(
+            "{}{} {}:{}:{}\n",
+            pad,
+            paint(GUTTER, "-->"),
+            origin,
+            line,
+            col
+        );
+        out += &format!("{} {}\n", pad, paint(GUTTER, "|"));
+        let line_txt = format!(
+            "{} {}",
+            paint(GUTTER, &format!("{} |", line_num)),
+            line_txt.trim_end_matches('\r')
+        );
+        out += line_txt.trim_end();
+        out += "\n";
+        out += &format!(
+            "{} {} {}{}\n",
+            pad,
+            paint(GUTTER, "|"),
+            " ".repeat(col - 1),
+            paint(ERROR, &"^".repeat(width))
+        );
+        if !self.notes.is_empty() || !self.help.is_empty() {
+            out += &format!("{} {}\n", pad, paint(GUTTER, "|"));
+        }
+        for note in &self.notes {
+            out += &format!(
+                "{} {} {} {}\n",
+                pad,
+                paint(GUTTER, "="),
+                paint(BOLD, "note:"),
+                note
+            );
+        }
+        for help in &self.help {
+            out += &format!(
+                "{} {} {} {}\n",
+                pad,
+                paint(GUTTER, "="),
+                paint(BOLD, "help:"),
+                help
+            );
+        }
+        out
+    }
+}
+
+pub fn render(err: &SteelErr, source: &str, origin: &str, style: Style) -> String {
+    Diagnostic::new(err, source).render(source, origin, style)
+}
+
+fn message(err: &SteelErr) -> String {
+    match err {
+        SteelErr::Located(err, _) => message(err),
+        SteelErr::ErrorExpected(_, expected) => format!("Expected {:?}", expected),
+        SteelErr::Multi(err, _) => message(err),
+        _ => format!("{}", err),
+    }
+}
+
+// Parse errors carry the rest of the input, which is a suffix of the source.
+fn locate_input(input: &str, source: &str) -> Option<Span> {
+    if !source.ends_with(input) {
+        return None;
+    }
+    let start = source.len() - input.len();
+    let token = input.find(char::is_whitespace).unwrap_or(input.len());
+    Some(Span::new(start, start + token))
+}
+
+fn locate(err: &SteelErr, source: &str) -> Option<Span> {
+    use SteelErr::*;
+    match err {
+        Located(_, span) => Some(*span),
+        ParserError { input, .. } => locate_input(input, source),
+        MalformedExpression(input, _) => locate_input(input, source),
+        MalformedInteger(input, _) => locate_input(input, source),
+        UnexpectedEndOfInput => Some(Span::new(source.len(), source.len())),
+        ErrorExpected(err, _) => locate(err, source),
+        Multi(a, b) => locate(a, source).or_else(|| locate(b, source)),
+        _ => None,
+    }
+}
+
+fn explain(diagnostic: &mut Diagnostic, err: &SteelErr) {
+    use SteelErr::*;
+    match err {
+        Located(err, _) => explain(diagnostic, err),
+        ErrorExpected(err, _) => {
+            diagnostic.notes.push(message(err));
+            explain(diagnostic, err);
+        }
+        Multi(a, b) => {
+            explain(diagnostic, a);
+            diagnostic.notes.push(format!("and {}", message(b)));
+            explain(diagnostic, b);
+        }
+        UnboundName(name) => diagnostic.help.push(format!(
+            "bind `{}` before using it, e.g. `f({}=1)`",
+            name, name
+        )),
+        MissingArgumentExpectedByExtern(func, arg) => diagnostic
+            .help
+            .push(format!("pass `{}` when calling `{}`", arg, func)),
+        MissingArgument(param) => diagnostic
+            .help
+            .push(format!("pass `{}` when calling the function", param)),
+        InvalidCondition(_) => diagnostic
+            .notes
+            .push("conditions must evaluate to `true` or `false`".to_string()),
+        TypeError { func, .. } => diagnostic.help.push(format!(
+            "check the kinds of the values passed to `{}`",
+            func
+        )),
+        WrongNumberOfArguments { func, expected, .. } => diagnostic.help.push(format!(
+            "pass {} arguments when calling `{}`",
+            expected, func
+        )),
+        ArithmeticError(crate::arithmetic::ArithmeticError::Overflow(_)) => diagnostic
+            .help
+            .push("use wrapping or saturating arithmetic to keep the result in range".to_string()),
+        ResourceLimitExceeded(_) => diagnostic
+            .help
+            .push("the program may not terminate, or raise the limit if it needs more".to_string()),
+        UnexpectedEndOfInput => diagnostic.help.push("add an expression".to_string()),
+        MalformedInteger(_, _) => diagnostic
+            .notes
+            .push("integers must fit in 64 bits".to_string()),
+        UnexpectedResult(_) => diagnostic
+            .help
+            .push("end the program with an expression that evaluates to a number".to_string()),
+        ReliedOnUninitializedMemory(_)
+        | ReliedOnOutOfBoundsMemory(_)
+        | UnknownNode(_)
+        | UnexpectedFrame(_)
+        | ArithmeticError(crate::arithmetic::ArithmeticError::NotArithmetic(_))
+        | MissingValueForBinding(_) => diagnostic.notes.push(
+            "this is a bug in the interpreter, please report it along with the program".to_string(),
+        ),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ast::Ast, handle, Tasks};
+
+    #[test]
+    fn renders_eval_errors_with_caret() {
+        let program = "1+(2*x)";
+        let err = crate::assert_is_err!(handle::<Ast>(Tasks::parse(program).and_eval()));
+        assert_eq!(
+            render(&err, program, "<input>", Style::Plain),
+            "error[E0017]: Expected a binding for x in scope
+ --> <input>:1:6
+  |
+1 | 1+(2*x)
+  |      ^
+  |
+  = help: bind `x` before using it, e.g. `f(x=1)`
+"
+        );
+    }
+
+    #[test]
+    fn renders_parse_errors_on_later_lines() {
+        let program = "12+\n3+#lol\n";
+        let err = crate::assert_is_err!(handle::<Ast>(Tasks::parse(program)));
+        assert_eq!(
+            render(&err, program, "test.steel", Style::Plain),
+            "error[E0011]: Expected the end of the input, found \"#lol\"
+ --> test.steel:2:3
+  |
+2 | 3+#lol
+  |   ^^^^
+"
+        );
+    }
+
+    #[test]
+    fn renders_errors_without_location() {
+        let err = SteelErr::ReliedOnUninitializedMemory(3);
+        assert_eq!(
+            render(&err, "", "<input>", Style::Plain),
+            "error[E0007]: Relied on uninitialized memory 3
+  = note: this is a bug in the interpreter, please report it along with the program
+"
+        );
+    }
+
+    #[test]
+    fn renders_unexpected_results() {
+        let err = crate::assert_is_err!(handle::<Ast>(Tasks::parse("putchar").and_eval()));
+        assert_eq!(
+            render(&err, "putchar", "<input>", Style::Plain),
+            "error[E0023]: Expected the program to evaluate to an i64, bool or unit, found extern#putchar
+  = help: end the program with an expression that evaluates to a number
+"
+        );
+    }
+
+    #[test]
+    fn renders_extra_notes_and_help() {
+        let err = SteelErr::UnexpectedEndOfInput;
+        let diagnostic = Diagnostic::new(&err, "").with_note("from the REPL");
+        assert_eq!(
+            diagnostic.render("", "<input>", Style::Plain),
+            "error[E0006]: Expected an expression, found nothing
+ --> <input>:1:1
+  |
+1 |
+  | ^
+  |
+  = note: from the REPL
+  = help: add an expression
+"
+        );
+    }
+
+    #[test]
+    fn renders_with_ansi_colours() {
+        let program = "1+x";
+        let err = crate::assert_is_err!(handle::<Ast>(Tasks::parse(program).and_eval()));
+        let rendered = render(&err, program, "<input>", Style::Ansi);
+        assert!(rendered.starts_with("\x1b[1;31merror[E0017]\x1b[0m"));
+        assert!(rendered.contains("\x1b[1;31m^\x1b[0m"));
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    ast::Ast, ecs::Ecs, handle_steps, interpreter::Evaluator, program_result, shrink::shrink,
+    CompilerContext, Limits, SteelErr, Tasks,
+};
+use std::path::{Path, PathBuf};
+
+// Enough for the generated programs, while still giving up quickly on ones that don't terminate.
+const MAX_STEPS: usize = 100_000;
+const MAX_DEPTH: usize = 1_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Ast,
+    Ecs,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub backend: Backend,
+    pub optimized: bool,
+}
+
+impl std::fmt::Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let backend = match self.backend {
+            Backend::Ast => "ast",
+            Backend::Ecs => "ecs",
+        };
+        let optimized = if self.optimized { " (optimized)" } else { "" };
+        write!(f, "{}{}", backend, optimized)
+    }
+}
+
+// Every way of running a program, which should all agree.
+pub const CONFIGS: [Config; 4] = [
+    Config {
+        backend: Backend::Ast,
+        optimized: false,
+    },
+    Config {
+        backend: Backend::Ecs,
+        optimized: false,
+    },
+    Config {
+        backend: Backend::Ast,
+        optimized: true,
+    },
+    Config {
+        backend: Backend::Ecs,
+        optimized: true,
+    },
+];
+
+// What running a program produced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub result: Result<i64, String>, // errors are compared without their location (which optimizing can move).
+    pub output: String,
+}
+
+fn message(err: &SteelErr) -> String {
+    match err {
+        SteelErr::Located(err, _) => message(err),
+        err => format!("{}", err),
+    }
+}
+
+fn run_in<Ctx: CompilerContext>(program: &str, optimized: bool) -> Option<Outcome>
+where
+    SteelErr: From<<Ctx as CompilerContext>::E>,
+{
+    let tasks = Tasks::parse(program);
+    let tasks = if optimized {
+        tasks.and_optimize()
+    } else {
+        tasks
+    };
+    let mut store = Ctx::new();
+    let expr = match handle_steps(&mut store, tasks) {
+        Ok((Some(expr), _result, _output)) => expr,
+        Ok((None, result, output)) => {
+            return Some(Outcome {
+                result: Ok(result),
+                output,
+            })
+        }
+        Err(err) => {
+            return Some(Outcome {
+                result: Err(message(&err)),
+                output: String::new(),
+            })
+        }
+    };
+    // Evaluated here (rather than by `handle`) to keep the output of runs that fail.
+    let limits = Limits::default().max_steps(MAX_STEPS).max_depth(MAX_DEPTH);
+    let mut evaluator = Evaluator::new(&store, expr).with_limits(limits);
+    let result = evaluator.run().and_then(|()| {
+        let index = evaluator.result_index().id;
+        program_result(evaluator.state().mem_stack.get(index), index)
+    });
+    let output = evaluator.output().to_string();
+    match result {
+        Err(SteelErr::ResourceLimitExceeded(_)) => None, // Inconclusive.
+        result => Some(Outcome {
+            result: result.map_err(|err| message(&err)),
+            output,
+        }),
+    }
+}
+
+// Run the program in the given configuration (or None if it ran out of steps or stack).
+pub fn run(program: &str, config: Config) -> Option<Outcome> {
+    match config.backend {
+        Backend::Ast => run_in::<Ast>(program, config.optimized),
+        Backend::Ecs => run_in::<Ecs>(program, config.optimized),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub program: String,
+    pub outcomes: Vec<(Config, Outcome)>,
+}
+
+// A reproducer, with the differing outcomes in comments.
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "// The configurations disagree about this program:")?;
+        for (config, outcome) in &self.outcomes {
+            writeln!(
+                f,
+                "// {}: {:?} with output {:?}",
+                config, outcome.result, outcome.output
+            )?;
+        }
+        writeln!(f, "{}", self.program)
+    }
+}
+
+impl Mismatch {
+    // Named by a hash of the program, so that re-running a failure doesn't make duplicates.
+    pub fn write_reproducer(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.program.hash(&mut hasher);
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{:016x}.steel", hasher.finish()));
+        std::fs::write(&path, format!("{}", self))?;
+        Ok(path)
+    }
+}
+
+// Where reproducers are written (overridden by `STEEL_REPRODUCER_DIR`).
+pub fn reproducer_dir() -> PathBuf {
+    std::env::var_os("STEEL_REPRODUCER_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("target/reproducers"))
+}
+
+fn compare(program: &str) -> Option<Mismatch> {
+    let outcomes: Vec<(Config, Outcome)> = CONFIGS
+        .iter()
+        .filter_map(|config| Some((*config, run(program, *config)?)))
+        .collect();
+    let (_config, first) = outcomes.first()?;
+    if outcomes.iter().all(|(_config, outcome)| outcome == first) {
+        return None;
+    }
+    Some(Mismatch {
+        program: program.to_string(),
+        outcomes,
+    })
+}
+
+fn minimise(mismatch: Mismatch) -> Mismatch {
+    let mut store = Ast::new();
+    let shrunk = crate::parser::program(&mut store, &mismatch.program)
+        .ok()
+        .and_then(|(_rest, root)| {
+            shrink(&mut store, root, |program| compare(program).is_some()).ok()
+        });
+    shrunk
+        .and_then(|program| compare(&program))
+        .unwrap_or(mismatch)
+}
+
+// Check that every configuration agrees about the program.
+pub fn check(program: &str) -> Result<(), Mismatch> {
+    match compare(program) {
+        None => Ok(()),
+        Some(mismatch) => Err(minimise(mismatch)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn configurations_agree() {
+        for program in [
+            "putchar(72)+putchar(105)",
+            "let x = 2*3; if x > 5 then x-1 else x+1",
+            "let f = (n) => if n <= 1 then 1 else n*self(n-1); f(10)",
+            "1/0",
+            "f(x)",
+            "((n) => self(n+1))(0)", // Never finishes (so it is skipped).
+        ] {
+            assert_eq!(check(program), Ok(()), "{}", program);
+        }
+        assert_eq!(run("((n) => self(n+1))(0)", CONFIGS[0]), None);
+        // Output is kept (and compared) even when the program fails.
+        for config in CONFIGS {
+            let outcome = Outcome {
+                result: Err("Division by zero".to_string()),
+                output: "A".to_string(),
+            };
+            assert_eq!(run("putchar(65)/0", config), Some(outcome), "{}", config);
+        }
+    }
+
+    #[test]
+    fn writes_reproducers() -> std::io::Result<()> {
+        let outcome = |result| Outcome {
+            result,
+            output: "A".to_string(),
+        };
+        let mismatch = Mismatch {
+            program: "65/putchar(65)".to_string(),
+            outcomes: vec![
+                (CONFIGS[0], outcome(Ok(1))),
+                (CONFIGS[2], outcome(Err("Division by zero".to_string()))),
+            ],
+        };
+        let dir = std::env::temp_dir().join("steel-reproducers-test");
+        let path = mismatch.write_reproducer(&dir)?;
+        let reproducer = std::fs::read_to_string(&path)?;
+        assert_eq!(
+            reproducer,
+            "// The configurations disagree about this program:
+// ast: Ok(1) with output \"A\"
+// ast (optimized): Err(\"Division by zero\") with output \"A\"
+65/putchar(65)
+"
+        );
+        // The comments are ignored when the reproducer is run.
+        assert_eq!(check(&reproducer), Ok(()));
+        std::fs::remove_file(path)
+    }
+}
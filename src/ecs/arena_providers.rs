@@ -12,6 +12,7 @@ pub trait ArenaProvider<T> {
     fn get_impl(&self, id: EntityId) -> Result<&T, EcsError>;
     fn get_mut_impl(&mut self, id: EntityId) -> Result<&mut T, EcsError>;
     fn remove_impl(&mut self, id: EntityId) -> Result<T, EcsError>;
+    fn attach_impl(&mut self, id: EntityId, value: T) -> Result<(), EcsError>;
 }
 
 impl<T, S: ArenaProvider<T>> Provider<T> for S {
@@ -22,8 +23,17 @@ impl<T, S: ArenaProvider<T>> Provider<T> for S {
         value: F,
     ) -> Result<(), EcsError> {
         let (entities, arena) = self.arena_mut();
+        // Spans and trivia outlive the node kind.
+        let Entity { span, trivia, .. } = *entities.get(id.id)?;
         let node: ComponentId<T> = ComponentId::new(arena.add((id, value(id)))); // ent id and ent component id.
-        entities.set(id.id, Self::make_entity(node))?;
+        entities.set(
+            id.id,
+            Entity {
+                span,
+                trivia,
+                ..Self::make_entity(node)
+            },
+        )?;
         Ok(())
     }
     fn add_with_id<F: FnOnce(EntityId) -> T>(&mut self, value: F) -> EntityId {
@@ -51,6 +61,9 @@ impl<T, S: ArenaProvider<T>> Provider<T> for S {
     fn remove_component_for_entity(&mut self, id: EntityId) -> Result<T, EcsError> {
         self.remove_impl(id)
     }
+    fn attach_component_to_entity(&mut self, id: EntityId, value: T) -> Result<(), EcsError> {
+        self.attach_impl(id, value)
+    }
 }
 
 #[macro_export]
@@ -114,6 +127,15 @@ macro_rules! make_arena_provider {
                     ))
                 }
             }
+            fn attach_impl(&mut self, id: EntityId, value: $type) -> Result<(), EcsError> {
+                if let Some(component_id) = self.entities.get(id.id)?.$kind {
+                    *self.get_component_mut(component_id)? = value;
+                } else {
+                    let component_id = ComponentId::new(self.$accessor.add((id, value)));
+                    self.entities.get_mut(id.id)?.$kind = Some(component_id);
+                }
+                Ok(())
+            }
         }
     };
 }
@@ -7,6 +7,15 @@ pub struct Entity {
     pub symbol: Option<ComponentId<Symbol>>,
     pub call: Option<ComponentId<Call<EntityId>>>,
     pub i_64: Option<ComponentId<i64>>,
+    pub f_64: Option<ComponentId<f64>>,
+    pub boolean: Option<ComponentId<bool>>,
+    pub string: Option<ComponentId<StrLiteral>>,
+    pub invalid: Option<ComponentId<Invalid>>,
+    pub let_binding: Option<ComponentId<Let<EntityId>>>,
+    pub lambda: Option<ComponentId<Lambda<EntityId>>>,
+    pub conditional: Option<ComponentId<If<EntityId>>>,
+    pub span: Option<ComponentId<Span>>,
+    pub trivia: Option<ComponentId<Trivia>>,
 }
 
 #[cfg(test)]
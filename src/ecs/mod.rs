@@ -18,15 +18,33 @@ use arena_providers::*;
 pub struct Ecs {
     entities: Arena<Entity>,
     i64_values: Arena<(EntityId, i64)>,
+    f64_values: Arena<(EntityId, f64)>,
+    bool_values: Arena<(EntityId, bool)>,
+    strings: Arena<(EntityId, StrLiteral)>,
     operators: Arena<(EntityId, Operator)>,
     symbols: Arena<(EntityId, Symbol)>,
     calls: Arena<(EntityId, Call<EntityId>)>,
+    invalids: Arena<(EntityId, Invalid)>,
+    let_bindings: Arena<(EntityId, Let<EntityId>)>,
+    lambdas: Arena<(EntityId, Lambda<EntityId>)>,
+    conditionals: Arena<(EntityId, If<EntityId>)>,
+    spans: Arena<(EntityId, Span)>,
+    trivia: Arena<(EntityId, Trivia)>,
 }
 
 make_arena_provider!(Ecs, i64, i_64, i64_values);
+make_arena_provider!(Ecs, f64, f_64, f64_values);
+make_arena_provider!(Ecs, bool, boolean, bool_values);
+make_arena_provider!(Ecs, StrLiteral, string, strings);
 make_arena_provider!(Ecs, Operator, operator, operators);
 make_arena_provider!(Ecs, Symbol, symbol, symbols);
 make_arena_provider!(Ecs, Call<EntityId>, call, calls);
+make_arena_provider!(Ecs, Invalid, invalid, invalids);
+make_arena_provider!(Ecs, Let<EntityId>, let_binding, let_bindings);
+make_arena_provider!(Ecs, Lambda<EntityId>, lambda, lambdas);
+make_arena_provider!(Ecs, If<EntityId>, conditional, conditionals);
+make_arena_provider!(Ecs, Span, span, spans);
+make_arena_provider!(Ecs, Trivia, trivia, trivia);
 
 impl CompilerContext for Ecs {
     type ID = EntityId;
@@ -35,22 +53,56 @@ impl CompilerContext for Ecs {
         Self::new()
     }
 
+    fn set_span(&mut self, id: Self::ID, span: Span) -> Result<(), Self::E> {
+        self.attach_component_to_entity(id, span)
+    }
+
+    fn get_span(&self, id: Self::ID) -> Result<&Span, Self::E> {
+        self.get_component_for_entity(id)
+    }
+
+    fn set_trivia(&mut self, id: Self::ID, trivia: Trivia) -> Result<(), Self::E> {
+        self.attach_component_to_entity(id, trivia)
+    }
+
+    fn get_trivia(&self, id: Self::ID) -> Result<&Trivia, Self::E> {
+        self.get_component_for_entity(id)
+    }
+
     fn active_mem_usage(&self) -> usize {
         std::mem::size_of::<Self>()
             + self.entities.active_mem_usage()
             + self.i64_values.active_mem_usage()
+            + self.f64_values.active_mem_usage()
+            + self.bool_values.active_mem_usage()
+            + self.strings.active_mem_usage()
             + self.operators.active_mem_usage()
             + self.symbols.active_mem_usage()
             + self.calls.active_mem_usage()
+            + self.invalids.active_mem_usage()
+            + self.let_bindings.active_mem_usage()
+            + self.lambdas.active_mem_usage()
+            + self.conditionals.active_mem_usage()
+            + self.spans.active_mem_usage()
+            + self.trivia.active_mem_usage()
     }
 
     fn mem_usage(&self) -> usize {
         std::mem::size_of::<Self>()
             + self.entities.mem_usage()
             + self.i64_values.mem_usage()
+            + self.f64_values.mem_usage()
+            + self.bool_values.mem_usage()
+            + self.strings.mem_usage()
             + self.operators.mem_usage()
             + self.symbols.mem_usage()
             + self.calls.mem_usage()
+            + self.invalids.mem_usage()
+            + self.let_bindings.mem_usage()
+            + self.lambdas.mem_usage()
+            + self.conditionals.mem_usage()
+            + self.spans.mem_usage()
+            + self.trivia.mem_usage()
     }
 
     fn for_each_i64<F: FnMut(&mut Self, Self::ID, &mut i64)>(
@@ -231,6 +283,45 @@ mod test {
         );
     }
 
+    #[test]
+    fn spans_survive_replace() -> Result<(), EcsError> {
+        let mut ctx: Ecs = Ecs::new();
+
+        let hello = ctx.add(Symbol::new("hello"));
+        ctx.set_span(hello, Span::new(3, 8))?;
+        ctx.replace(hello, 12i64)?;
+
+        assert_eq!(ctx.get::<i64>(hello)?, &12);
+        assert_eq!(ctx.get_span(hello)?, &Span::new(3, 8));
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_replace_missing_node() {
+        let mut other: Ecs = Ecs::new();
+        let hello = other.add(Symbol::new("hello"));
+        let mut ctx: Ecs = Ecs::new();
+
+        assert!(ctx.replace(hello, 12i64).is_err());
+    }
+
+    #[test]
+    fn trivia_survives_replace() -> Result<(), EcsError> {
+        let mut ctx: Ecs = Ecs::new();
+
+        let hello = ctx.add(Symbol::new("hello"));
+        let trivia = Trivia {
+            leading: vec!["/* hi */".to_string()],
+            trailing: vec![],
+        };
+        ctx.set_trivia(hello, trivia.clone())?;
+        ctx.replace(hello, 12i64)?;
+
+        assert_eq!(ctx.get::<i64>(hello)?, &12);
+        assert_eq!(ctx.get_trivia(hello)?, &trivia);
+        Ok(())
+    }
+
     #[test]
     fn can_construct_nodes_with_self_reference() {
         let mut ctx: Ecs = Ecs::new();
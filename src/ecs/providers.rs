@@ -36,6 +36,7 @@ pub trait Provider<T> {
     fn remove_component_for_entity(&mut self, id: EntityId) -> Result<T, EcsError>;
     fn get_component_for_entity(&self, id: EntityId) -> Result<&T, EcsError>;
     fn get_component_for_entity_mut(&mut self, id: EntityId) -> Result<&mut T, EcsError>;
+    fn attach_component_to_entity(&mut self, id: EntityId, value: T) -> Result<(), EcsError>;
 }
 
 #[cfg(test)]
@@ -1,3 +1,4 @@
+use crate::nodes::Span;
 use nom::error::ParseError;
 
 #[must_use]
@@ -6,14 +7,34 @@ pub enum SteelErr {
     IOError(std::io::Error),
     AstError(crate::ast::AstError),
     EcsError(crate::ecs::EcsError),
-    PrecedenceError {
-        precendence: i32,
-    },
     UnexpectedEndOfInput,
     ReliedOnUninitializedMemory(usize),
     ReliedOnOutOfBoundsMemory(usize),
     MissingArgumentExpectedByExtern(String, String),
     MissingValueForBinding(String),
+    MissingArgument(String),
+    EvaluatedInvalidNode(String),
+    InvalidCondition(String),
+    TypeError {
+        func: String,
+        arg: String,
+        expected: String,
+        found: String,
+    },
+    UnboundName(String),
+    ResourceLimitExceeded(crate::interpreter::Limit),
+    Cancelled,
+    WrongNumberOfArguments {
+        func: String,
+        expected: usize,
+        found: usize,
+    },
+    HostError(String),
+    ArithmeticError(crate::arithmetic::ArithmeticError),
+    UnexpectedResult(String),
+    UnknownNode(String),
+    IncompleteInput(Option<usize>),
+    UnexpectedFrame(String),
     MalformedExpression(String, String),
     ParserError {
         input: String,
@@ -21,6 +42,7 @@ pub enum SteelErr {
     }, // Parse
     ErrorExpected(Box<SteelErr>, String),
     Multi(Box<SteelErr>, Box<SteelErr>),
+    Located(Box<SteelErr>, Span),
 }
 
 impl std::fmt::Debug for SteelErr {
@@ -32,11 +54,6 @@ impl std::fmt::Debug for SteelErr {
 impl std::fmt::Display for SteelErr {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            PrecedenceError { precendence } => write!(
-                f,
-                "Unexpected operator due to max precendence setting ({})",
-                precendence
-            ),
             MalformedInteger(input, error) => {
                 write!(f, "Failed to parse int due to {:?} in {}", error, input)
             }
@@ -52,8 +69,51 @@ impl std::fmt::Display for SteelErr {
                 write!(f, "Expected argument {} for {}", arg, func)
             }
             MissingValueForBinding(name) => write!(f, "Expected value for {}", name),
+            MissingArgument(param) => write!(f, "Expected an argument for parameter {}", param),
+            EvaluatedInvalidNode(message) => {
+                write!(f, "Cannot evaluate code that failed to parse: {}", message)
+            }
+            InvalidCondition(value) => {
+                write!(f, "Expected a bool as the condition, found {}", value)
+            }
+            TypeError {
+                func,
+                arg,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Expected {} for argument {} of {}, found {}",
+                expected, arg, func, found
+            ),
+            UnboundName(name) => write!(f, "Expected a binding for {} in scope", name),
+            ResourceLimitExceeded(limit) => write!(f, "Exceeded the limit of {}", limit),
+            Cancelled => write!(f, "Evaluation was cancelled"),
+            WrongNumberOfArguments {
+                func,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Expected {} arguments for {}, found {}",
+                expected, func, found
+            ),
+            HostError(message) => write!(f, "{}", message),
+            ArithmeticError(err) => write!(f, "{}", err),
+            UnexpectedResult(found) => write!(
+                f,
+                "Expected the program to evaluate to an i64, bool or unit, found {}",
+                found
+            ),
+            UnknownNode(id) => write!(f, "Cannot evaluate unknown node {}", id),
+            IncompleteInput(Some(needed)) => {
+                write!(f, "Expected {} more bytes of input", needed)
+            }
+            IncompleteInput(None) => write!(f, "Expected more input"),
+            UnexpectedFrame(frame) => write!(f, "Cannot run the {} frame here", frame),
             MalformedExpression(input, expected) => {
-                write!(f, "Expected {}, found {:?}", expected, input)
+                let found = input.split('\n').next().unwrap_or(input);
+                write!(f, "Expected {}, found {:?}", expected, found)
             }
             ParserError { input, code } => {
                 write!(f, "Failed in {:?} while parsing {}", code, input)
@@ -64,12 +124,70 @@ impl std::fmt::Display for SteelErr {
             AstError(e) => write!(f, "{:?}", e),
             EcsError(e) => write!(f, "{:?}", e),
             Multi(a, b) => write!(f, "{}\nand {}", a, b),
+            Located(err, span) => write!(f, "{} at {}", err, span),
         }
     }
 }
 
 use SteelErr::*;
 
+impl SteelErr {
+    // Attach the location of the node that caused the error (keeping the innermost location).
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            Located(_, _) => self,
+            _ => Located(Box::new(self), span),
+        }
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Located(_, span) => Some(span),
+            _ => None,
+        }
+    }
+
+    // Stable identifiers for each kind of error (used when rendering diagnostics).
+    pub fn code(&self) -> &'static str {
+        match self {
+            MalformedInteger(_, _) => "E0001",
+            IOError(_) => "E0002",
+            AstError(_) => "E0003",
+            EcsError(_) => "E0004",
+            UnexpectedEndOfInput => "E0006",
+            ReliedOnUninitializedMemory(_) => "E0007",
+            ReliedOnOutOfBoundsMemory(_) => "E0008",
+            MissingArgumentExpectedByExtern(_, _) => "E0009",
+            MissingValueForBinding(_) => "E0010",
+            MalformedExpression(_, _) => "E0011",
+            ParserError { .. } => "E0012",
+            EvaluatedInvalidNode(_) => "E0013",
+            MissingArgument(_) => "E0014",
+            InvalidCondition(_) => "E0015",
+            TypeError { .. } => "E0016",
+            UnboundName(_) => "E0017",
+            ResourceLimitExceeded(_) => "E0018",
+            Cancelled => "E0019",
+            WrongNumberOfArguments { .. } => "E0020",
+            HostError(_) => "E0021",
+            ArithmeticError(_) => "E0022",
+            UnexpectedResult(_) => "E0023",
+            UnknownNode(_) => "E0024",
+            IncompleteInput(_) => "E0025",
+            UnexpectedFrame(_) => "E0026",
+            ErrorExpected(err, _) => err.code(),
+            Multi(err, _) => err.code(),
+            Located(err, _) => err.code(),
+        }
+    }
+}
+
+impl From<crate::arithmetic::ArithmeticError> for SteelErr {
+    fn from(err: crate::arithmetic::ArithmeticError) -> Self {
+        ArithmeticError(err)
+    }
+}
+
 impl From<std::io::Error> for SteelErr {
     fn from(err: std::io::Error) -> Self {
         IOError(err)
@@ -93,7 +211,8 @@ impl From<nom::Err<SteelErr>> for SteelErr {
         match err {
             nom::Err::Error(e) => e,
             nom::Err::Failure(e) => e,
-            nom::Err::Incomplete(_needed) => todo!("Handle incomplete input"),
+            nom::Err::Incomplete(nom::Needed::Size(needed)) => IncompleteInput(Some(needed.get())),
+            nom::Err::Incomplete(nom::Needed::Unknown) => IncompleteInput(None),
         }
     }
 }
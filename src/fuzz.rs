@@ -0,0 +1,345 @@
+use crate::{
+    ast::Ast,
+    ecs::Ecs,
+    gen_code::{generate_random_program, Spec, Weights},
+    handle,
+    parser::{program, program_recovering, program_with_trivia},
+    CompilerContext, Limits, SteelErr, Tasks,
+};
+use rand::Rng;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+// Fuzz targets, which panic if they find a bug.
+// They can be run with `cargo fuzz` (see `fuzz/`) or with the mutation loop in `steel-fuzz`.
+pub type Target = fn(&[u8]);
+
+pub const TARGETS: [(&str, Target); 5] = [
+    ("parse", parse),
+    ("parse_trivia", parse_trivia),
+    ("parse_recovering", parse_recovering),
+    ("round_trip", round_trip),
+    ("eval", eval),
+];
+
+// Small enough to keep each run quick, even for programs that don't terminate.
+const MAX_STEPS: usize = 10_000;
+const MAX_DEPTH: usize = 1_000;
+
+fn parse_in<Ctx: CompilerContext>(source: &str) -> Option<String>
+where
+    SteelErr: From<<Ctx as CompilerContext>::E>,
+{
+    let mut store = Ctx::new();
+    let (_rest, root) = program(&mut store, source).ok()?;
+    Some(store.pretty(root))
+}
+
+// Parsing shouldn't panic (whether or not the input is a program).
+pub fn parse(data: &[u8]) {
+    let source = String::from_utf8_lossy(data);
+    parse_in::<Ast>(&source);
+    parse_in::<Ecs>(&source);
+}
+
+fn parse_trivia_in<Ctx: CompilerContext>(source: &str)
+where
+    SteelErr: From<<Ctx as CompilerContext>::E>,
+{
+    let mut store = Ctx::new();
+    if let Ok((_rest, root)) = program_with_trivia(&mut store, source) {
+        store.pretty(root);
+    }
+}
+
+// Parsing (and printing) with comments shouldn't panic.
+pub fn parse_trivia(data: &[u8]) {
+    let source = String::from_utf8_lossy(data);
+    parse_trivia_in::<Ast>(&source);
+    parse_trivia_in::<Ecs>(&source);
+}
+
+fn parse_recovering_in<Ctx: CompilerContext>(source: &str)
+where
+    SteelErr: From<<Ctx as CompilerContext>::E>,
+{
+    let mut store = Ctx::new();
+    let (root, errors) = program_recovering(&mut store, source);
+    if let Some(root) = root {
+        store.pretty(root);
+    }
+    if parse_in::<Ctx>(source).is_some() {
+        assert!(root.is_some(), "{:?} parsed without a tree", source);
+        assert!(
+            errors.is_empty(),
+            "{:?} parsed with errors {:?}",
+            source,
+            errors
+        );
+    }
+}
+
+// Recovering from errors shouldn't panic, and shouldn't find any in programs that parse.
+pub fn parse_recovering(data: &[u8]) {
+    let source = String::from_utf8_lossy(data);
+    parse_recovering_in::<Ast>(&source);
+    parse_recovering_in::<Ecs>(&source);
+}
+
+fn round_trip_in<Ctx: CompilerContext>(source: &str)
+where
+    SteelErr: From<<Ctx as CompilerContext>::E>,
+{
+    if let Some(pretty) = parse_in::<Ctx>(source) {
+        let again = parse_in::<Ctx>(&pretty);
+        assert_eq!(
+            Some(&pretty),
+            again.as_ref(),
+            "{:?} was pretty printed as {:?}",
+            source,
+            pretty
+        );
+    }
+}
+
+// Pretty printing a program should give a program that pretty prints the same way.
+pub fn round_trip(data: &[u8]) {
+    let source = String::from_utf8_lossy(data);
+    round_trip_in::<Ast>(&source);
+    round_trip_in::<Ecs>(&source);
+}
+
+fn eval_in<Ctx: CompilerContext>(source: &str)
+where
+    SteelErr: From<<Ctx as CompilerContext>::E>,
+{
+    let limits = Limits::default().max_steps(MAX_STEPS).max_depth(MAX_DEPTH);
+    let _ = handle::<Ctx>(Tasks::parse(source).and_eval().and_limits(limits));
+}
+
+// Evaluating shouldn't panic (though it can fail).
+pub fn eval(data: &[u8]) {
+    let source = String::from_utf8_lossy(data);
+    eval_in::<Ast>(&source);
+    eval_in::<Ecs>(&source);
+}
+
+// Bits of syntax to splice into inputs, so that mutations tend to stay close to programs.
+const TOKENS: &[&str] = &[
+    "let ", "=", ";", "if ", " then ", " else ", "(", ")", "=>", ",", "+", "-", "*", "/", "==",
+    "!=", "<", "<=", ">", ">=", "&&", "||", "!", "~", "true", "false", "\"", "\\", "1.5", "0", "7",
+    "x", "arg_0", "putchar", "len", "to_i64", "self", "//", "/*", "*/", "\n", "é", "日本", "🦀",
+];
+
+// Change the input a little: by flipping, inserting or removing bytes, inserting chars or tokens
+// or duplicating part of it.
+pub fn mutate<R: Rng + ?Sized>(input: &mut Vec<u8>, rng: &mut R) {
+    let at = rng.gen_range(0..=input.len());
+    match rng.gen_range(0..6) {
+        0 if at < input.len() => input[at] ^= 1 << rng.gen_range(0..8),
+        1 => input.insert(at, rng.gen()),
+        2 if at < input.len() => {
+            let end = rng.gen_range(at..=input.len());
+            input.drain(at..end);
+        }
+        3 if at < input.len() => {
+            let end = rng.gen_range(at..=input.len());
+            let part = input[at..end].to_vec();
+            let to = rng.gen_range(0..=input.len());
+            input.splice(to..to, part);
+        }
+        4 => {
+            let c: char = rng.gen(); // Any char (most of which aren't ASCII).
+            input.splice(at..at, c.to_string().into_bytes());
+        }
+        _ => {
+            let token = TOKENS[rng.gen_range(0..TOKENS.len())];
+            input.splice(at..at, token.bytes());
+        }
+    }
+}
+
+// Some programs to start mutating from: a few that use each kind of syntax and random ones.
+pub fn seed_corpus<R: Rng + ?Sized>(rng: &mut R) -> Vec<Vec<u8>> {
+    let mut corpus: Vec<Vec<u8>> = [
+        "putchar(48+9)",
+        "let x = 3; if x < 4 && !(x == 2) then x else -x",
+        "let f = (n) => if n <= 1 then 1 else n*self(n-1); f(5)",
+        "((a, b) => a/b)(b=2, a=~7)",
+        "to_i64(1.5*2.0)+len(\"abc\\n\")",
+        "// A comment\n1",
+    ]
+    .iter()
+    .map(|program| program.as_bytes().to_vec())
+    .collect();
+    for weights in [Weights::default(), Weights::all_nodes()] {
+        for size in 1..20 {
+            let spec = Spec::default().sized(size).weighted(weights.clone());
+            let spec = if size % 2 == 0 {
+                spec.well_typed()
+            } else {
+                spec
+            };
+            let mut store = Ast::new();
+            let root = generate_random_program("corpus", &mut store, &spec, rng);
+            corpus.push(store.pretty(root).into_bytes());
+        }
+    }
+    corpus
+}
+
+// An input that made a target panic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Crash {
+    pub input: Vec<u8>,
+    pub message: String,
+}
+
+impl Crash {
+    // Named by a hash of the input, so that finding the same crash again doesn't make duplicates.
+    pub fn write(
+        &self,
+        dir: &std::path::Path,
+        target: &str,
+    ) -> std::io::Result<std::path::PathBuf> {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.input.hash(&mut hasher);
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}-{:016x}", target, hasher.finish()));
+        std::fs::write(&path, &self.input)?;
+        Ok(path)
+    }
+}
+
+fn try_target(target: Target, input: &[u8]) -> Result<(), Crash> {
+    catch_unwind(AssertUnwindSafe(|| target(input))).map_err(|payload| {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.to_string()
+        } else {
+            "(no message)".to_string()
+        };
+        Crash {
+            input: input.to_vec(),
+            message,
+        }
+    })
+}
+
+// Run the target on the corpus and then on `iterations` mutations of it, returning the first
+// crash. Inputs that parse are added to the corpus (as a cheap stand in for coverage).
+// Panics abort rather than unwind in release builds, so this needs a debug build to catch them.
+pub fn fuzz<R: Rng + ?Sized>(
+    target: Target,
+    mut corpus: Vec<Vec<u8>>,
+    iterations: usize,
+    rng: &mut R,
+) -> Result<(), Crash> {
+    for input in &corpus {
+        try_target(target, input)?;
+    }
+    if corpus.is_empty() {
+        corpus.push(vec![]);
+    }
+    for _ in 0..iterations {
+        let mut input = corpus[rng.gen_range(0..corpus.len())].clone();
+        for _ in 0..rng.gen_range(1..=4) {
+            mutate(&mut input, rng);
+        }
+        try_target(target, &input)?;
+        if corpus.len() < 10_000 && parse_in::<Ast>(&String::from_utf8_lossy(&input)).is_some() {
+            corpus.push(input);
+        }
+    }
+    Ok(())
+}
+
+// The mutation loop, for when libFuzzer isn't available.
+pub fn run() {
+    env_logger::init();
+    let mut args = std::env::args();
+    let _program_path = args.next();
+    let (name, iterations) = match (args.next(), args.next(), args.next()) {
+        (Some(name), iterations, None) => (name, iterations.map(|n| n.parse())),
+        _ => (String::new(), None),
+    };
+    let target = TARGETS.iter().find(|(target, _)| *target == name);
+    let (target, iterations) = match (target, iterations) {
+        (Some((_name, target)), None) => (*target, 100_000),
+        (Some((_name, target)), Some(Ok(iterations))) => (*target, iterations),
+        _ => {
+            let names: Vec<&str> = TARGETS.iter().map(|(name, _)| *name).collect();
+            eprintln!("usage: steel-fuzz {} [ITERATIONS]", names.join("|"));
+            std::process::exit(1);
+        }
+    };
+    let seed = crate::gen_code::seed_from_env();
+    eprintln!("fuzzing {} with STEEL_SEED={}", name, seed);
+    let mut rng = Spec::default().seeded(seed).rng();
+    let corpus = seed_corpus(&mut rng);
+    std::panic::set_hook(Box::new(|_info| {})); // Crashes are reported below.
+    if let Err(crash) = fuzz(target, corpus, iterations, &mut rng) {
+        let _ = std::panic::take_hook();
+        eprintln!("{}", crash.message);
+        eprintln!("input: {:?}", String::from_utf8_lossy(&crash.input));
+        match crash.write(std::path::Path::new("target/fuzz-crashes"), &name) {
+            Ok(path) => eprintln!("written to {}", path.display()),
+            Err(err) => eprintln!("could not write the input: {}", err),
+        }
+        std::process::exit(1);
+    }
+    eprintln!("no crashes in {} iterations", iterations);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gen_code::seed_from_env;
+
+    #[test]
+    fn finds_crashes() {
+        let seed = seed_from_env();
+        let mut rng = Spec::default().seeded(seed).rng();
+        let target: Target = |data| assert!(!data.contains(&b'~'), "found a ~");
+        let crash = match fuzz(target, vec![b"1+2".to_vec()], 10_000, &mut rng) {
+            Ok(()) => panic!("no crash found (replay with STEEL_SEED={})", seed),
+            Err(crash) => crash,
+        };
+        assert!(crash.input.contains(&b'~'));
+        assert_eq!(crash.message, "found a ~");
+    }
+
+    #[test]
+    fn targets_handle_the_hand_written_corpus() {
+        let corpus = seed_corpus(&mut Spec::default().seeded(0).rng());
+        for (name, target) in TARGETS {
+            for input in &corpus[..6] {
+                assert_eq!(try_target(target, input), Ok(()), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn targets_handle_non_ascii_text() {
+        let inputs = ["héllo", "f(\"日本\", é) // ü", "/* 🦀 */ x", "1 + \"\\é"];
+        for (name, target) in TARGETS {
+            for input in inputs {
+                assert_eq!(try_target(target, input.as_bytes()), Ok(()), "{}", name);
+            }
+        }
+        let mut rng = Spec::default().seeded(seed_from_env()).rng();
+        let mut input = b"1+2".to_vec();
+        for _ in 0..1_000 {
+            mutate(&mut input, &mut rng);
+        }
+        assert!(!input.is_ascii());
+    }
+
+    #[test]
+    fn eval_handles_programs_that_return_functions() {
+        for input in ["-", "putchar", "len", "<", "(x) => x", "1.5", "\"a\""] {
+            assert_eq!(try_target(eval, input.as_bytes()), Ok(()), "{}", input);
+        }
+    }
+}
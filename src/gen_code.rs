@@ -1,24 +1,68 @@
 use crate::{
-    nodes::{Call, Operator, Symbol},
+    nodes::{Call, If, Lambda, Let, Operator, StrLiteral, Symbol},
     CompilerContext,
 };
-use log::trace;
-use rand::{distributions::Alphanumeric, rngs::ThreadRng, Rng};
+use log::{trace, warn};
+use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
 
-static CHANCE_OF_POTENTIALLY_LARGE_CONSTANT: f64 = 0.01;
-static CHANCE_OF_SYMBOL: f64 = 0.99;
-static CHANCE_OF_NAMED_ARG: f64 = 0.15;
-static CHANCE_OF_CALL_WITH_NO_ARGS: f64 = 0.15;
-
-fn weighted_bool(rng: &mut ThreadRng, chance: f64) -> bool {
+fn weighted_bool<R: Rng + ?Sized>(rng: &mut R, chance: f64) -> bool {
     rng.gen_range(0f64..=1f64) < chance
 }
 
+// How likely the generator is to pick each kind of node (as chances between 0 and 1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weights {
+    pub potentially_large_constant: f64,
+    pub symbol: f64,
+    pub named_arg: f64,
+    pub call_with_no_args: f64,
+    // Kinds of node that aren't generated by default (so that default programs always evaluate).
+    pub operator: f64, // including externs like `putchar`.
+    pub binding: f64,
+    pub conditional: f64,
+    pub literal: f64, // bools, floats and strings.
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            potentially_large_constant: 0.01,
+            symbol: 0.99,
+            named_arg: 0.15,
+            call_with_no_args: 0.15,
+            operator: 0.0,
+            binding: 0.0,
+            conditional: 0.0,
+            literal: 0.0,
+        }
+    }
+}
+
+impl Weights {
+    // Generate every kind of node (e.g. for fuzzing).
+    pub fn all_nodes() -> Self {
+        Self {
+            operator: 0.3,
+            binding: 0.1,
+            conditional: 0.1,
+            literal: 0.1,
+            ..Self::default()
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Spec {
     pub size: Option<usize>,
+    pub seed: Option<u64>,
+    pub weights: Weights,
+    pub max_depth: Option<usize>,
+    pub max_arity: Option<usize>,
+    pub well_typed: bool,
     name: String,
     in_scope: Vec<Spec>,
+    depth: usize,
+    variables: Vec<String>, // bound by `let`s and lambdas in the generated program.
 }
 
 impl Spec {
@@ -26,7 +70,14 @@ impl Spec {
         Self {
             name: name.to_string(),
             size: None,
+            seed: None,
+            weights: Weights::default(),
+            max_depth: None,
+            max_arity: None,
+            well_typed: false,
             in_scope: Vec::new(),
+            depth: 0,
+            variables: Vec::new(),
         }
     }
 
@@ -57,6 +108,44 @@ impl Spec {
         self
     }
 
+    // Generate the same program every time (see `rng`).
+    pub fn seeded(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn weighted(mut self, weights: Weights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    // How deeply nodes can be nested (after which only leaves are generated).
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    // How many arguments each call can have.
+    pub fn max_arity(mut self, max_arity: usize) -> Self {
+        self.max_arity = Some(max_arity);
+        self
+    }
+
+    // Only generate programs without type errors (e.g. no calls to non-callables like `0(1)`):
+    // calls are to lambdas, operators get arguments of the right type and conditions are bools.
+    pub fn well_typed(mut self) -> Self {
+        self.well_typed = true;
+        self
+    }
+
+    // A random number generator for this spec, which is reproducible if the spec is seeded.
+    pub fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
     fn is_in_scope(&self, context: &Spec) -> bool {
         // TODO: check if all the required args are in `context`.
         for req in &self.in_scope {
@@ -73,46 +162,193 @@ impl Spec {
         trace!("Missing symbol {}", self.name);
         false
     }
+
+    fn child(&self, size: usize) -> Self {
+        let mut child = self.clone().sized(size);
+        child.depth += 1;
+        child
+    }
+
+    fn bind(mut self, name: &str) -> Self {
+        self.variables.push(name.to_string());
+        self
+    }
+
+    fn at_max_depth(&self) -> bool {
+        self.max_depth
+            .is_some_and(|max_depth| self.depth >= max_depth)
+    }
+
+    fn max_args(&self, max_args: usize) -> usize {
+        self.max_arity
+            .map_or(max_args, |max_arity| max_arity.min(max_args))
+    }
+
+    // The operators and externs in scope that fit in `size` nodes (and return a `ty`, if given).
+    fn operators(&self, size: usize, ty: Option<Type>) -> Vec<(String, &'static [Type])> {
+        self.in_scope
+            .iter()
+            .filter_map(|s| {
+                let (result, args) = signature(&s.name)?;
+                let fits = args.len() < size && args.len() <= self.max_args(args.len());
+                (fits && ty.is_none_or(|ty| ty == result)).then(|| (s.name.to_string(), args))
+            })
+            .collect()
+    }
 }
 
 impl Default for Spec {
     fn default() -> Self {
-        Self::symbol("main")
+        let mut spec = Self::symbol("main")
             .sized(100)
             .add_symbol(Spec::symbol("putchar").add_symbol(Spec::symbol("arg_0")))
-            .add_symbol(Spec::operator("+", 2))
-            .add_symbol(Spec::operator("*", 2))
-            .add_symbol(Spec::operator("/", 2))
-            .add_symbol(Spec::operator("-", 2))
+            .add_symbol(Spec::symbol("len").add_symbol(Spec::symbol("arg_0")))
+            .add_symbol(Spec::symbol("to_i64").add_symbol(Spec::symbol("arg_0")));
+        for op in Operator::all() {
+            let name = op.to_str();
+            let duplicate = spec.in_scope.iter().any(|s| s.name == name); // e.g. `-` for `Neg`.
+            if let (Some((_result, args)), false) = (signature(name), duplicate) {
+                spec = spec.add_symbol(Spec::operator(name, args.len()));
+            }
+        }
+        spec
+    }
+}
+
+// The seed from the `STEEL_SEED` environment variable (e.g. to replay a failure), or a new one.
+pub fn seed_from_env() -> u64 {
+    let var = std::env::var("STEEL_SEED").ok();
+    match parse_seed(var.as_deref()) {
+        Ok(seed) => seed,
+        Err(err) => {
+            let seed = rand::random();
+            warn!("{}, using STEEL_SEED={} instead", err, seed);
+            seed
+        }
+    }
+}
+
+// A new seed if there isn't one, or an error if it isn't a u64.
+fn parse_seed(var: Option<&str>) -> Result<u64, String> {
+    match var {
+        Some(seed) => seed
+            .trim()
+            .parse()
+            .map_err(|_| format!("Expected STEEL_SEED to be a u64, found {:?}", seed)),
+        None => Ok(rand::random()),
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Type {
+    I64,
+    Bool,
+    F64,
+    Str,
+}
+
+// The result and argument types of the operators and externs that the generator can call.
+fn signature(name: &str) -> Option<(Type, &'static [Type])> {
+    use Type::*;
+    Some(match name {
+        "+" | "-" | "*" | "/" => (I64, &[I64, I64]),
+        "~" => (I64, &[I64]),
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => (Bool, &[I64, I64]),
+        "&&" | "||" => (Bool, &[Bool, Bool]),
+        "!" => (Bool, &[Bool]),
+        "putchar" => (I64, &[I64]),
+        "len" => (I64, &[Str]),
+        "to_i64" => (I64, &[F64]),
+        _ => return None,
+    })
+}
+
+// Split `size` nodes between `parts` children (each getting at least one).
+fn split<R: Rng + ?Sized>(rng: &mut R, size: usize, parts: usize) -> Vec<usize> {
+    let mut sizes = vec![1; parts];
+    for _ in parts..size {
+        sizes[rng.gen_range(0..parts)] += 1;
+    }
+    sizes
+}
+
+// Names that generated programs can't bind: the keywords, and `self` (which every call binds).
+const RESERVED: [&str; 7] = ["if", "then", "else", "let", "true", "false", "self"];
+
+fn random_name<R: Rng + ?Sized>(rng: &mut R) -> String {
+    loop {
+        let tail: String = rng
+            .sample_iter(&Alphanumeric)
+            .take(3)
+            .map(char::from)
+            .collect();
+        let name = rng.gen_range('a'..='z').to_string() + &tail;
+        if !RESERVED.contains(&name.as_str()) {
+            return name;
+        }
+    }
+}
+
+fn add_symbol<Ctx: CompilerContext>(store: &mut Ctx, name: &str) -> Ctx::ID {
+    match Operator::all().iter().find(|op| op.to_str() == name) {
+        Some(op) => store.add(*op),
+        None => store.add(Symbol::new(name)),
     }
 }
 
-pub fn generate_random_program<Ctx: CompilerContext>(
+fn add_operator_call<Ctx: CompilerContext>(
+    store: &mut Ctx,
+    name: &str,
+    args: Vec<Ctx::ID>,
+) -> Ctx::ID {
+    let callee = add_symbol(store, name);
+    let args = args
+        .into_iter()
+        .enumerate()
+        .map(|(index, arg)| (format!("arg_{}", index), arg))
+        .collect();
+    store.add(Call::new(callee, args))
+}
+
+pub fn generate_random_program<Ctx: CompilerContext, R: Rng + ?Sized>(
     _name: &'static str,
     store: &mut Ctx,
     spec: &Spec,
-    rng: &mut ThreadRng,
+    rng: &mut R,
 ) -> Ctx::ID {
     // eprintln!(">> {}", store.pretty(r));
-    generate_random_program_impl::<Ctx>(_name, store, spec, rng)
+    if spec.well_typed {
+        return generate_typed(store, spec, Type::I64, rng);
+    }
+    generate_random_program_impl::<Ctx, R>(_name, store, spec, rng)
 }
 
-pub fn generate_random_program_impl<Ctx: CompilerContext>(
+pub fn generate_random_program_impl<Ctx: CompilerContext, R: Rng + ?Sized>(
     _name: &'static str,
     store: &mut Ctx,
     spec: &Spec,
-    rng: &mut ThreadRng,
+    rng: &mut R,
 ) -> Ctx::ID {
     let size = spec.size.unwrap_or_else(|| rng.gen_range(1..1000));
+    if let Some(id) = generate_other_node(_name, store, spec, size, rng) {
+        return id;
+    }
     let mut args_size: usize = rng.gen_range(0..=size);
     let arg_range = (args_size as f64).sqrt() as usize;
-    let num_args: usize = rng.gen_range(0..=arg_range);
-    if size > args_size && (num_args > 0 || weighted_bool(rng, CHANCE_OF_CALL_WITH_NO_ARGS)) {
-        let mut starter = spec.clone();
+    let num_args: usize = rng.gen_range(0..=spec.max_args(arg_range));
+    if size > args_size
+        && !spec.at_max_depth()
+        && (num_args > 0 || weighted_bool(rng, spec.weights.call_with_no_args))
+    {
+        let mut starter = spec.child(size);
         starter.in_scope = starter
             .in_scope
             .drain(0..)
-            .filter(|s| s.name.starts_with("arg_"))
+            .filter(|s| {
+                // Operators are only kept if they will be called (rather than used as values).
+                s.name.starts_with("arg_")
+                    || spec.weights.operator > 0.0 && signature(&s.name).is_some()
+            })
             .collect();
         args_size -= num_args; // at least one node per arg.
         let mut args = vec![];
@@ -120,13 +356,8 @@ pub fn generate_random_program_impl<Ctx: CompilerContext>(
         let mut inner_spec = starter.clone().named("self".to_string()).sized(inner_size);
         let mut arg_index = 0;
         for _ in 0..num_args {
-            let tail: String = rng
-                .sample_iter(&Alphanumeric)
-                .take(3)
-                .map(char::from)
-                .collect();
-            let arg_name = if weighted_bool(rng, CHANCE_OF_NAMED_ARG) {
-                rng.gen_range('a'..='z').to_string() + &tail
+            let arg_name = if weighted_bool(rng, spec.weights.named_arg) {
+                random_name(rng)
             } else {
                 let s = format!("arg_{}", arg_index);
                 arg_index += 1;
@@ -144,29 +375,204 @@ pub fn generate_random_program_impl<Ctx: CompilerContext>(
         return store.add(Call::new(callee, args));
     }
     trace!("in scope: {:?}", &spec.in_scope);
-    let symbols: Vec<Spec> = spec
+    let mut symbols: Vec<&str> = spec
         .in_scope
         .iter()
         .filter(|s| s.is_in_scope(spec))
-        .cloned()
+        .map(|s| &*s.name)
         .collect();
-    if !symbols.is_empty() && weighted_bool(rng, CHANCE_OF_SYMBOL) {
+    symbols.extend(spec.variables.iter().map(|name| &**name));
+    if !symbols.is_empty() && weighted_bool(rng, spec.weights.symbol) {
         let symbol_index: usize = rng.gen_range(0..symbols.len());
-        let spec = &symbols[symbol_index];
-        return match &*spec.name {
-            "+" => store.add(Operator::Add),
-            "-" => store.add(Operator::Sub),
-            "*" => store.add(Operator::Mul),
-            "/" => store.add(Operator::Div),
-            _ => store.add(Symbol {
-                name: spec.name.to_string(),
-            }),
-        };
+        return add_symbol(store, symbols[symbol_index]);
+    }
+    if weighted_bool(rng, spec.weights.literal) {
+        let ty = [Type::Bool, Type::F64, Type::Str][rng.gen_range(0..3)];
+        return generate_literal(store, ty, rng);
+    }
+    generate_i64(store, spec, rng)
+}
+
+// The kinds of node that the default programs don't use (with children of any type).
+fn generate_other_node<Ctx: CompilerContext, R: Rng + ?Sized>(
+    _name: &'static str,
+    store: &mut Ctx,
+    spec: &Spec,
+    size: usize,
+    rng: &mut R,
+) -> Option<Ctx::ID> {
+    if size < 2 || spec.at_max_depth() {
+        return None;
+    }
+    let children = |store: &mut Ctx, rng: &mut R, parts| -> Vec<Ctx::ID> {
+        split(rng, size - 1, parts)
+            .into_iter()
+            .map(|size| generate_random_program(_name, store, &spec.child(size), rng))
+            .collect()
+    };
+    let operators = spec.operators(size, None);
+    if !operators.is_empty() && weighted_bool(rng, spec.weights.operator) {
+        let (name, args) = &operators[rng.gen_range(0..operators.len())];
+        let args = children(store, rng, args.len());
+        return Some(add_operator_call(store, name, args));
+    }
+    if size > 2 && weighted_bool(rng, spec.weights.binding) {
+        let name = random_name(rng);
+        let sizes = split(rng, size - 1, 2);
+        let value = generate_random_program(_name, store, &spec.child(sizes[0]), rng);
+        let body_spec = spec.child(sizes[1]).bind(&name);
+        let body = generate_random_program(_name, store, &body_spec, rng);
+        return Some(store.add(Let::new(&name, value, body)));
+    }
+    if size > 3 && weighted_bool(rng, spec.weights.conditional) {
+        let parts = children(store, rng, 3);
+        return Some(store.add(If::new(parts[0], parts[1], parts[2])));
     }
-    let value: i64 = if weighted_bool(rng, CHANCE_OF_POTENTIALLY_LARGE_CONSTANT) {
+    None
+}
+
+fn generate_i64<Ctx: CompilerContext, R: Rng + ?Sized>(
+    store: &mut Ctx,
+    spec: &Spec,
+    rng: &mut R,
+) -> Ctx::ID {
+    let value: i64 = if weighted_bool(rng, spec.weights.potentially_large_constant) {
         rng.gen() // some potentially large constant.
     } else {
         rng.gen_range(-5i64..=5i64) // some small value.
     };
     store.add(value)
 }
+
+fn generate_literal<Ctx: CompilerContext, R: Rng + ?Sized>(
+    store: &mut Ctx,
+    ty: Type,
+    rng: &mut R,
+) -> Ctx::ID {
+    match ty {
+        Type::I64 => store.add(rng.gen_range(-5i64..=5i64)),
+        Type::Bool => store.add(rng.gen::<bool>()),
+        Type::F64 => store.add(rng.gen_range(-10..=10) as f64 / 4.0),
+        Type::Str => {
+            let len = rng.gen_range(0..5);
+            let value: String = rng
+                .sample_iter(&Alphanumeric)
+                .take(len)
+                .map(char::from)
+                .collect();
+            store.add(StrLiteral::new(&value))
+        }
+    }
+}
+
+// Generate a program that evaluates to a `ty` without type errors (though it can still fail,
+// e.g. by dividing by zero).
+fn generate_typed<Ctx: CompilerContext, R: Rng + ?Sized>(
+    store: &mut Ctx,
+    spec: &Spec,
+    ty: Type,
+    rng: &mut R,
+) -> Ctx::ID {
+    let size = spec.size.unwrap_or_else(|| rng.gen_range(1..1000));
+    if ty == Type::F64 || ty == Type::Str || size < 2 || spec.at_max_depth() {
+        return generate_typed_leaf(store, spec, ty, rng);
+    }
+    let children = |store: &mut Ctx, rng: &mut R, types: &[Type]| -> Vec<Ctx::ID> {
+        split(rng, size - 1, types.len())
+            .into_iter()
+            .zip(types)
+            .map(|(size, ty)| generate_typed(store, &spec.child(size), *ty, rng))
+            .collect()
+    };
+    let operators = spec.operators(size, Some(ty));
+    if !operators.is_empty() && weighted_bool(rng, spec.weights.operator) {
+        let (name, args) = &operators[rng.gen_range(0..operators.len())];
+        let args = children(store, rng, args);
+        return add_operator_call(store, name, args);
+    }
+    if size > 2 && weighted_bool(rng, spec.weights.binding) {
+        let name = random_name(rng);
+        let sizes = split(rng, size - 1, 2);
+        let value = generate_typed(store, &spec.child(sizes[0]), Type::I64, rng);
+        let body = generate_typed(store, &spec.child(sizes[1]).bind(&name), ty, rng);
+        return store.add(Let::new(&name, value, body));
+    }
+    if size > 3 && weighted_bool(rng, spec.weights.conditional) {
+        let parts = children(store, rng, &[Type::Bool, ty, ty]);
+        return store.add(If::new(parts[0], parts[1], parts[2]));
+    }
+    // Like the calls in untyped programs, but the callee is a lambda (so that it can be called).
+    let num_args = rng.gen_range(0..=spec.max_args(((size - 1) as f64).sqrt() as usize));
+    if num_args > 0 || weighted_bool(rng, spec.weights.call_with_no_args) {
+        let sizes = split(rng, size - 1, num_args + 1);
+        let mut body_spec = spec.child(sizes[0]);
+        let mut args = vec![];
+        for size in &sizes[1..] {
+            let name = random_name(rng);
+            let arg = generate_typed(store, &spec.child(*size), Type::I64, rng);
+            args.push((name.to_string(), arg));
+            body_spec = body_spec.bind(&name);
+        }
+        let body = generate_typed(store, &body_spec, ty, rng);
+        let params = args.iter().map(|(name, _arg)| name.to_string()).collect();
+        let callee = store.add(Lambda::new(params, body));
+        return store.add(Call::new(callee, args));
+    }
+    generate_typed_leaf(store, spec, ty, rng)
+}
+
+fn generate_typed_leaf<Ctx: CompilerContext, R: Rng + ?Sized>(
+    store: &mut Ctx,
+    spec: &Spec,
+    ty: Type,
+    rng: &mut R,
+) -> Ctx::ID {
+    if ty == Type::I64 && !spec.variables.is_empty() && weighted_bool(rng, spec.weights.symbol) {
+        let name = &spec.variables[rng.gen_range(0..spec.variables.len())];
+        return store.add(Symbol::new(name));
+    }
+    match ty {
+        Type::I64 => generate_i64(store, spec, rng),
+        ty => generate_literal(store, ty, rng),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_seeds() {
+        assert_eq!(parse_seed(Some("42")).ok(), Some(42));
+        assert_eq!(
+            parse_seed(Some("forty-two")).unwrap_err(),
+            "Expected STEEL_SEED to be a u64, found \"forty-two\""
+        );
+        assert!(parse_seed(None).is_ok());
+    }
+
+    #[test]
+    fn names_are_not_keywords() {
+        // Whether `name` can be bound and then used.
+        let binds = |name: &str| {
+            let mut store = crate::ast::Ast::new();
+            let source = format!("let {} = 1; {}", name, name);
+            match crate::parser::program(&mut store, &source) {
+                Ok(("", root)) => store.get_let(root).is_ok_and(|binding| {
+                    let body = store.get_symbol(binding.body);
+                    binding.name == name && body.is_ok_and(|symbol| symbol.name == name)
+                }),
+                _ => false,
+            }
+        };
+        for word in ["if", "let", "true", "false"] {
+            assert!(!binds(word), "{}", word);
+        }
+        let mut rng = Spec::default().seeded(seed_from_env()).rng();
+        for _ in 0..10_000 {
+            let name = random_name(&mut rng);
+            assert!(!RESERVED.contains(&name.as_str()), "{}", name);
+            assert!(binds(&name), "{}", name);
+        }
+    }
+}
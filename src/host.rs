@@ -0,0 +1,302 @@
+use crate::error::SteelErr;
+use crate::interpreter::{type_error, Environment, Impl, Value};
+
+// Values that host functions can take as arguments.
+pub trait FromValue<ID>: Sized {
+    const KIND: &'static str; // what was expected (used in type errors).
+    fn from_value(value: Value<ID>) -> Result<Self, Value<ID>>;
+}
+
+// Values that host functions can return (including errors).
+pub trait IntoValue<ID> {
+    fn into_value(self) -> Result<Value<ID>, SteelErr>;
+}
+
+macro_rules! value_conversions {
+    ($($t:ty => $kind:literal as $variant:ident),*) => {
+        $(
+            impl<ID> FromValue<ID> for $t {
+                const KIND: &'static str = $kind;
+                fn from_value(value: Value<ID>) -> Result<Self, Value<ID>> {
+                    match value {
+                        Value::$variant(v) => Ok(v),
+                        other => Err(other),
+                    }
+                }
+            }
+
+            impl<ID> IntoValue<ID> for $t {
+                fn into_value(self) -> Result<Value<ID>, SteelErr> {
+                    Ok(Value::$variant(self))
+                }
+            }
+        )*
+    };
+}
+
+value_conversions!(
+    i64 => "i64" as I64,
+    f64 => "f64" as F64,
+    bool => "bool" as Bool,
+    String => "str" as Str
+);
+
+impl<ID> FromValue<ID> for () {
+    const KIND: &'static str = "unit";
+    fn from_value(value: Value<ID>) -> Result<Self, Value<ID>> {
+        match value {
+            Value::Unit => Ok(()),
+            other => Err(other),
+        }
+    }
+}
+
+impl<ID> IntoValue<ID> for () {
+    fn into_value(self) -> Result<Value<ID>, SteelErr> {
+        Ok(Value::Unit)
+    }
+}
+
+// Any value (e.g. for functions that inspect the kind themselves).
+impl<ID> FromValue<ID> for Value<ID> {
+    const KIND: &'static str = "a value";
+    fn from_value(value: Value<ID>) -> Result<Self, Value<ID>> {
+        Ok(value)
+    }
+}
+
+impl<ID> IntoValue<ID> for Value<ID> {
+    fn into_value(self) -> Result<Value<ID>, SteelErr> {
+        Ok(self)
+    }
+}
+
+impl<ID, T: IntoValue<ID>> IntoValue<ID> for Result<T, SteelErr> {
+    fn into_value(self) -> Result<Value<ID>, SteelErr> {
+        self?.into_value()
+    }
+}
+
+// Rust closures that can be called from steel, e.g. `|l: i64, r: i64| l.max(r)`.
+// `Args` is the tuple of argument types (so that closures of each arity can be registered).
+pub trait HostFn<ID, Args>: 'static {
+    const ARITY: usize;
+    fn call(&mut self, func: &str, args: Vec<(String, Value<ID>)>) -> Result<Value<ID>, SteelErr>;
+}
+
+fn wrong_arity(func: &str, expected: usize, found: usize) -> SteelErr {
+    SteelErr::WrongNumberOfArguments {
+        func: func.to_string(),
+        expected,
+        found,
+    }
+}
+
+fn convert<ID: std::fmt::Debug, T: FromValue<ID>>(
+    func: &str,
+    (param, value): (String, Value<ID>),
+) -> Result<T, SteelErr> {
+    T::from_value(value).map_err(|found| type_error(func, &param, T::KIND, &found))
+}
+
+macro_rules! host_fn {
+    ($arity:literal; $($arg:ident),*) => {
+        impl<ID, F, R, $($arg,)*> HostFn<ID, ($($arg,)*)> for F
+        where
+            ID: std::fmt::Debug,
+            F: FnMut($($arg),*) -> R + 'static,
+            R: IntoValue<ID>,
+            $($arg: FromValue<ID>,)*
+        {
+            const ARITY: usize = $arity;
+
+            #[allow(non_snake_case, unused_mut, unused_variables)] // for closures without arguments.
+            fn call(
+                &mut self,
+                func: &str,
+                args: Vec<(String, Value<ID>)>,
+            ) -> Result<Value<ID>, SteelErr> {
+                let found = args.len();
+                let mut args = args.into_iter();
+                let mut next = || args.next().ok_or_else(|| wrong_arity(func, $arity, found));
+                $(let $arg = convert::<ID, $arg>(func, next()?)?;)*
+                self($($arg),*).into_value()
+            }
+        }
+    };
+}
+
+host_fn!(0;);
+host_fn!(1; A);
+host_fn!(2; A, B);
+host_fn!(3; A, B, C);
+host_fn!(4; A, B, C, D);
+
+impl<ID: Clone + std::fmt::Debug + 'static> Environment<ID> {
+    // Make a Rust closure callable from steel as `name`, with the given parameter names.
+    // Arguments can be passed by name or by position (e.g. `clamp(v=12, lo=0, hi=10)` or `clamp(12, 0, 10)`).
+    // Fails if the number of parameter names doesn't match the closure's arity.
+    pub fn register<Args, F: HostFn<ID, Args>>(
+        self,
+        name: &str,
+        params: &[&str],
+        mut f: F,
+    ) -> Result<Self, SteelErr> {
+        if params.len() != F::ARITY {
+            return Err(SteelErr::HostError(format!(
+                "{} takes {} arguments, but was given {} parameter names",
+                name,
+                F::ARITY,
+                params.len()
+            )));
+        }
+        let func = name.to_string();
+        let params: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+        Ok(self.with_extern(Impl::with_args(name, move |state, args| {
+            if args.len() != params.len() {
+                return Err(wrong_arity(&func, params.len(), args.len()));
+            }
+            let mut values = Vec::new();
+            for (arg_num, param) in params.iter().enumerate() {
+                let positional = format!("arg_{}", arg_num);
+                let (_name, index) = args
+                    .iter()
+                    .find(|(name, _index)| name == param)
+                    .or_else(|| args.iter().find(|(name, _index)| *name == positional))
+                    .ok_or_else(|| {
+                        SteelErr::MissingArgumentExpectedByExtern(
+                            func.to_string(),
+                            param.to_string(),
+                        )
+                    })?;
+                values.push((param.to_string(), state.get_mem(*index)?.clone()));
+            }
+            f.call(&func, values)
+        })))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ast::Ast, ecs::Ecs, handle, CompilerContext, Tasks};
+
+    fn environment<ID: Clone + std::fmt::Debug + 'static>() -> Result<Environment<ID>, SteelErr> {
+        let mut count = 0;
+        Environment::default()
+            .register("clamp", &["v", "lo", "hi"], |v: i64, lo: i64, hi: i64| {
+                v.clamp(lo, hi)
+            })?
+            .register("shout", &["s"], |s: String| s.to_uppercase() + "!")?
+            .register("checked_div", &["l", "r"], |l: i64, r: i64| {
+                l.checked_div(r)
+                    .ok_or_else(|| SteelErr::HostError(format!("cannot divide {} by {}", l, r)))
+            })?
+            .register("count", &[], move || {
+                count += 1;
+                count
+            })
+    }
+
+    fn eval<Ctx: CompilerContext>(program: &str) -> Result<i64, SteelErr>
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        let tasks = Tasks::parse(program)
+            .and_eval()
+            .and_environment(environment()?);
+        Ok(handle::<Ctx>(tasks)?.1)
+    }
+
+    fn calls_host_functions<Ctx: CompilerContext>() -> Result<(), SteelErr>
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        assert_eq!(eval::<Ctx>("clamp(12, 0, 10)")?, 10);
+        assert_eq!(eval::<Ctx>("clamp(hi=10, v=-3, lo=0)")?, 0);
+        assert_eq!(eval::<Ctx>("len(shout(\"hey\"))")?, 4);
+        assert_eq!(eval::<Ctx>("checked_div(7, 2)")?, 3);
+        assert_eq!(eval::<Ctx>("count()*10+count()")?, 12);
+        assert_err_is!(
+            eval::<Ctx>("clamp(1, 2)"),
+            "Expected 3 arguments for clamp, found 2"
+        );
+        assert_err_is!(
+            eval::<Ctx>("clamp(1, 2, top=3)"),
+            "Expected argument hi for clamp"
+        );
+        assert_err_is!(
+            eval::<Ctx>("clamp(1.5, 0, 10)"),
+            "Expected i64 for argument v of clamp, found 1.5_f64"
+        );
+        assert_err_is!(eval::<Ctx>("checked_div(1, 0)"), "cannot divide 1 by 0");
+        Ok(())
+    }
+
+    #[test]
+    fn calls_host_functions_ast() -> Result<(), SteelErr> {
+        calls_host_functions::<Ast>()
+    }
+
+    #[test]
+    fn calls_host_functions_ecs() -> Result<(), SteelErr> {
+        calls_host_functions::<Ecs>()
+    }
+
+    #[test]
+    fn reports_host_functions_that_panicked() {
+        let environment = Environment::default().register("boom", &[], || -> i64 {
+            panic!("boom");
+        });
+        let environment = environment.unwrap();
+        let mut store = Ast::new();
+        let (_, root) = crate::parser::program(&mut store, "boom()+boom()").unwrap();
+        let mut evaluator = crate::Evaluator::with_environment(&store, root, environment);
+        let run = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| evaluator.run()));
+        assert!(run.is_err());
+        // The function's state may be broken, so it isn't called again.
+        assert_err_is!(evaluator.run(), "boom panicked on an earlier call");
+    }
+
+    #[test]
+    fn empty_environment_has_no_externs() {
+        let tasks = Tasks::parse("1+2")
+            .and_eval()
+            .and_environment(Environment::empty().register("one", &[], || 1_i64).unwrap());
+        assert_err_is!(
+            handle::<Ast>(tasks),
+            "Expected a binding for + in scope at 1..2"
+        );
+        let tasks = Tasks::parse("one()")
+            .and_eval()
+            .and_environment(Environment::empty().register("one", &[], || 1_i64).unwrap());
+        assert_eq!(handle::<Ast>(tasks).unwrap().1, 1);
+    }
+
+    #[test]
+    fn optimizer_keeps_overridden_operators() -> Result<(), SteelErr> {
+        let minus = || Environment::default().register("+", &["l", "r"], |l: i64, r: i64| l - r);
+        for tasks in [Tasks::parse("1+2"), Tasks::parse("1+2").and_optimize()] {
+            let tasks = tasks.and_eval().and_environment(minus()?);
+            assert_eq!(handle::<Ast>(tasks)?.1, -1);
+        }
+        let tasks = Tasks::parse("1+2").and_optimize().and_eval();
+        assert_err_is!(
+            handle::<Ast>(tasks.and_environment(Environment::empty())),
+            "Expected a binding for + in scope at 1..2"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parameter_names_must_match_arity() {
+        assert_err_is!(
+            Environment::<usize>::empty().register(
+                "clamp",
+                &["v", "lo"],
+                |v: i64, lo: i64, _hi: i64| v.max(lo),
+            ),
+            "clamp takes 3 arguments, but was given 2 parameter names"
+        );
+    }
+}
@@ -9,7 +9,6 @@ use ntest::timeout;
 #[derive(Default, Debug)]
 pub struct Case {
     txt: Option<String>,
-    no_round_trip: bool,
     prints_as: Option<String>,
     error_is: Option<String>,
 }
@@ -19,10 +18,6 @@ impl Case {
         self.txt = Some(txt.to_string());
         self
     }
-    fn no_round_trip(mut self) -> Self {
-        self.no_round_trip = true;
-        self
-    }
     fn error_is(mut self, error_is: &str) -> Self {
         self.error_is = Some(error_is.to_string());
         self
@@ -55,12 +50,12 @@ where
     };
     assert_eq!(left_over, "", "Expected to parse full input");
     eprint!("  program: {:?}", result);
-    eprintln!(" value={:?}", ctx.get_call(result)?);
+    eprintln!(" value={:?}", ctx.get_call(result));
     let pretty = ctx.pretty(result);
     eprintln!(" as_tree={}", &pretty);
     if let Some(prints_as) = &case.prints_as {
         assert_eq!(&pretty, prints_as, "Is expected to print as");
-    } else if !case.no_round_trip {
+    } else {
         assert_eq!(&pretty, txt, "Is expected to round trip");
     }
     eprintln!(
@@ -77,7 +72,7 @@ glasses_harness!(ParserTest, Case, |case: Case| {
     run_test("Ecs", &case, Ecs::new()).expect("Ast failed");
 });
 
-glasses_test!(ParserTest, handle_white_space, [timeout(10)], expr "-123\n", prints_as "0-123");
+glasses_test!(ParserTest, handle_white_space, [timeout(10)], expr "-123\n", prints_as "-123");
 glasses_test!(
     ParserTest,
     handle_malformed_with_white_space,
@@ -87,13 +82,32 @@ glasses_test!(
 );
 glasses_test!(ParserTest, simple_plus, [timeout(10)], expr "12+23");
 glasses_test!(ParserTest, simple_plus_with_trailing, [timeout(10)], expr "12+23");
-glasses_test!(ParserTest, unary_in_parens, [timeout(10)], expr "*(12)", no_round_trip);
-glasses_test!(ParserTest, unary_no_parens, [timeout(10)], expr "*12", no_round_trip);
+glasses_test!(ParserTest, unary_in_parens, [timeout(10)], expr "*(12)", prints_as "(*)(12)");
+glasses_test!(
+    ParserTest,
+    unary_no_parens,
+    [timeout(10)],
+    expr "*12",
+    error_is "Expected a prefix operator (one of \"!\", \"-\", \"~\"), found \"*12\""
+);
+glasses_test!(ParserTest, negation, [timeout(10)], expr "-x");
+glasses_test!(ParserTest, negation_binds_tightly, [timeout(10)], expr "-x*y+1");
+glasses_test!(ParserTest, negation_of_sum, [timeout(10)], expr "-(x+1)");
+glasses_test!(ParserTest, negation_twice, [timeout(10)], expr "--x");
+glasses_test!(ParserTest, subtract_negation, [timeout(10)], expr "a--b");
+glasses_test!(ParserTest, negative_literal, [timeout(10)], expr "a-(-3)");
+glasses_test!(ParserTest, negation_of_literal, [timeout(10)], expr "-(3)");
+glasses_test!(ParserTest, negation_of_literal_on_new_line, [timeout(10)], expr "a--\n3", prints_as "a--(3)");
+glasses_test!(ParserTest, bitwise_not, [timeout(10)], expr "~x&&!y");
+glasses_test!(ParserTest, operator_value, [timeout(10)], expr "f(op=(-), -)");
 glasses_test!(ParserTest, func_call, [timeout(10)], expr "foo(12, a)");
 glasses_test!(ParserTest, op_call, [timeout(10)], expr "+(12, 23)", prints_as "12+23");
-glasses_test!(ParserTest, multi_op, [timeout(10)], expr "(12+23+34)", no_round_trip);
-glasses_test!(ParserTest, prec_mul_add, [timeout(10)], expr "12*23+34", prints_as "(12*23)+34");
-glasses_test!(ParserTest, prec_add_mul, [timeout(10)], expr "12+23*34", prints_as "12+(23*34)");
+glasses_test!(ParserTest, multi_op, [timeout(10)], expr "(12+23+34)", prints_as "12+23+34");
+glasses_test!(ParserTest, multi_op_no_parens, [timeout(10)], expr "12+23+34");
+glasses_test!(ParserTest, left_assoc_keeps_parens, [timeout(10)], expr "12-(23-34)");
+glasses_test!(ParserTest, left_assoc_drops_parens, [timeout(10)], expr "(12-23)-34", prints_as "12-23-34");
+glasses_test!(ParserTest, prec_mul_add, [timeout(10)], expr "12*23+34");
+glasses_test!(ParserTest, prec_add_mul, [timeout(10)], expr "12+23*34");
 glasses_test!(
     ParserTest,
     prec_mul_paren_add,
@@ -108,4 +122,171 @@ glasses_test!(
     expr "(12+23)*34",
     prints_as "(12+23)*34"
 );
-glasses_test!(ParserTest, prec_hard_case2, [timeout(10)], expr "a+b*c+d", prints_as "(a+(b*c))+d");
+glasses_test!(ParserTest, prec_hard_case2, [timeout(10)], expr "a+b*c+d");
+glasses_test!(ParserTest, let_binding, [timeout(10)], expr "let x = 1; x+2");
+glasses_test!(
+    ParserTest,
+    let_nested,
+    [timeout(10)],
+    expr "let x = 1; let y = x*2; y+x"
+);
+glasses_test!(ParserTest, let_in_op, [timeout(10)], expr "1+(let x = 2; x)");
+glasses_test!(ParserTest, let_in_args, [timeout(10)], expr "f(a=let x = 2; x)");
+glasses_test!(
+    ParserTest,
+    let_without_body,
+    [timeout(10)],
+    expr "let x = 1;",
+    error_is "Expected an expression, found nothing"
+);
+glasses_test!(ParserTest, lambda, [timeout(10)], expr "(x, y) => x+y");
+glasses_test!(ParserTest, lambda_no_params, [timeout(10)], expr "() => 1");
+glasses_test!(ParserTest, lambda_call, [timeout(10)], expr "((x) => x*2)(3)");
+glasses_test!(ParserTest, lambda_arg, [timeout(10)], expr "map((x) => x*2, xs)");
+glasses_test!(
+    ParserTest,
+    lambda_in_let,
+    [timeout(10)],
+    expr "let sq = (x) => x*x; sq(3)"
+);
+glasses_test!(ParserTest, comparison, [timeout(10)], expr "x<=3");
+glasses_test!(ParserTest, equality, [timeout(10)], expr "x==y", prints_as "x==y");
+glasses_test!(ParserTest, not, [timeout(10)], expr "!x");
+glasses_test!(
+    ParserTest,
+    prec_cmp_add,
+    [timeout(10)],
+    expr "x+1<y*2"
+);
+glasses_test!(
+    ParserTest,
+    prec_and_or,
+    [timeout(10)],
+    expr "a||b&&c==d"
+);
+glasses_test!(ParserTest, prec_or_in_and, [timeout(10)], expr "(a||b)&&c");
+glasses_test!(
+    ParserTest,
+    non_assoc_comparison,
+    [timeout(10)],
+    expr "a<b<c",
+    error_is "Expected parentheses as \"<\" is non-associative, found \"<c\""
+);
+glasses_test!(
+    ParserTest,
+    equality_arg_is_not_binding,
+    [timeout(10)],
+    expr "f(x==1)"
+);
+glasses_test!(ParserTest, conditional, [timeout(10)], expr "if x then 1 else 2");
+glasses_test!(
+    ParserTest,
+    conditional_nested,
+    [timeout(10)],
+    expr "if x<1 then 0 else if x<10 then 1 else 2"
+);
+glasses_test!(
+    ParserTest,
+    conditional_in_op,
+    [timeout(10)],
+    expr "1+(if x then 1 else 2)"
+);
+glasses_test!(
+    ParserTest,
+    conditional_without_else,
+    [timeout(10)],
+    expr "if x then 1",
+    error_is "Expected keyword \"else\", found \"\""
+);
+glasses_test!(
+    ParserTest,
+    bools,
+    [timeout(10)],
+    expr "true&&!false"
+);
+glasses_test!(ParserTest, not_binds_tightly, [timeout(10)], expr "!a&&b");
+glasses_test!(ParserTest, not_of_and, [timeout(10)], expr "!(a&&b)");
+glasses_test!(ParserTest, string, [timeout(10)], expr "print(\"hello world\")");
+glasses_test!(
+    ParserTest,
+    string_escapes,
+    [timeout(10)],
+    expr "\"tab\\tquote\\\"slash\\\\\""
+);
+glasses_test!(ParserTest, string_with_parens, [timeout(10)], expr "concat(\"(\", \",)\")");
+glasses_test!(
+    ParserTest,
+    string_unterminated,
+    [timeout(10)],
+    expr "\"abc",
+    error_is "Expected a closing quote for the string, found \"\\\"abc\""
+);
+glasses_test!(ParserTest, float, [timeout(10)], expr "1.5");
+glasses_test!(ParserTest, float_op, [timeout(10)], expr "0.5*x+2.25");
+glasses_test!(ParserTest, float_negative, [timeout(10)], expr "f(-1.5)");
+glasses_test!(ParserTest, float_exponent, [timeout(10)], expr "2e10", prints_as "20000000000.0");
+glasses_test!(ParserTest, float_small, [timeout(10)], expr "1.5e-7");
+glasses_test!(
+    ParserTest,
+    float_too_large,
+    [timeout(10)],
+    expr "1e999",
+    error_is "Expected a finite float, found \"1e999\""
+);
+glasses_test!(
+    ParserTest,
+    line_comment,
+    [timeout(10)],
+    expr "1+ // one\n2",
+    prints_as "1+2"
+);
+glasses_test!(
+    ParserTest,
+    block_comment,
+    [timeout(10)],
+    expr "f(/* a, */ b)",
+    prints_as "f(b)"
+);
+glasses_test!(
+    ParserTest,
+    comment_only,
+    [timeout(10)],
+    expr "// nothing here",
+    error_is "Expected an expression, found nothing"
+);
+glasses_test!(
+    ParserTest,
+    unterminated_block_comment,
+    [timeout(10)],
+    expr "1+/* 2",
+    error_is "Expected the end of the block comment, found \"/* 2\""
+);
+glasses_test!(
+    ParserTest,
+    newline_ends_let_value,
+    [timeout(10)],
+    expr "let x = 1\nx+1",
+    prints_as "let x = 1; x+1"
+);
+glasses_test!(
+    ParserTest,
+    newline_ends_let_value_before_operator,
+    [timeout(10)],
+    expr "let x = 1\n*2; x",
+    error_is "Expected a prefix operator (one of \"!\", \"-\", \"~\"), found \"*2; x\""
+);
+glasses_test!(
+    ParserTest,
+    newline_in_brackets_continues_let_value,
+    [timeout(10)],
+    expr "let x = f(1,\n2)+(3\n*4)\nx",
+    prints_as "let x = f(1, 2)+3*4; x"
+);
+glasses_test!(
+    ParserTest,
+    newline_outside_let_continues,
+    [timeout(10)],
+    expr "1\n+2",
+    prints_as "1+2"
+);
+glasses_test!(ParserTest, paren_symbol_is_not_lambda, [timeout(10)], expr "(x)+1", prints_as "x+1");
@@ -1,26 +1,46 @@
+use crate::arithmetic::Arithmetic;
 use crate::compiler_context::CompilerContext;
 use crate::error::SteelErr;
+use crate::io::{BufferIo, Io};
+use crate::nodes::Operator::{self, Add, Div, Mul, Sub};
 use crate::typed_index::TypedIndex;
 use log::{debug, error, trace};
-use std::collections::HashMap;
+use std::cmp::Ordering::{self, Equal, Greater, Less};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
 
-type Imp<ID> = Arc<Mutex<dyn FnMut(&mut EvalState<ID>) -> Result<Value<ID>, SteelErr>>>;
+type Args<ID> = [(String, MemIndex<ID>)];
+type Imp<ID> = Arc<Mutex<dyn FnMut(&mut EvalState<ID>, &Args<ID>) -> Result<Value<ID>, SteelErr>>>;
 
 #[derive(Clone)]
 pub struct Impl<ID> {
-    name: &'static str,
+    name: String,
     imp: Imp<ID>,
+    builtin: bool, // i.e. one that the optimizer may evaluate ahead of time.
 }
 
 impl<ID> Impl<ID> {
+    // Builtins find their arguments by name (e.g. `arg_0`).
     fn new<F: 'static + FnMut(&mut EvalState<ID>) -> Result<Value<ID>, SteelErr>>(
-        name: &'static str,
-        imp: F,
+        name: &str,
+        mut imp: F,
     ) -> Self {
         Self {
-            name,
+            builtin: true,
+            ..Self::with_args(name, move |state, _args| imp(state))
+        }
+    }
+
+    // Externs that are passed the arguments of the call (i.e. name -> index in memory).
+    pub(crate) fn with_args<F>(name: &str, imp: F) -> Self
+    where
+        F: 'static + FnMut(&mut EvalState<ID>, &Args<ID>) -> Result<Value<ID>, SteelErr>,
+    {
+        Self {
+            name: name.to_string(),
             imp: Arc::new(Mutex::new(imp)),
+            builtin: false,
         }
     }
 }
@@ -31,30 +51,77 @@ impl<ID> std::fmt::Debug for Impl<ID> {
     }
 }
 
+#[derive(Clone)]
+pub struct Closure<ID> {
+    code: ID,                              // the lambda to run.
+    captured: Vec<(String, MemIndex<ID>)>, // bindings (of the names it uses) when the lambda was evaluated.
+}
+
 #[derive(Clone)]
 pub enum Value<ID> {
     Uninit,
-    I64(i64), // a raw i64 value.
-    // TODO: Func(ID), // reference to an 'expression' like thing that can be evaluated in some context.
-    Extern(Impl<ID>), // reference to an extern...
+    Unit,              // the result of expressions that are only run for their effects.
+    Bool(bool),        // a raw bool value.
+    I64(i64),          // a raw i64 value.
+    F64(f64),          // a raw f64 value.
+    Str(String),       // an owned string.
+    Func(Closure<ID>), // reference to a lambda that can be evaluated in its captured context.
+    Extern(Impl<ID>),  // reference to an extern...
 }
 
-impl<ID> std::fmt::Debug for Value<ID> {
+impl<ID> Value<ID> {
+    // The name of the kind of value (used in type errors).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Uninit => "uninit",
+            Value::Unit => "unit",
+            Value::Bool(_) => "bool",
+            Value::I64(_) => "i64",
+            Value::F64(_) => "f64",
+            Value::Str(_) => "str",
+            Value::Func(_) => "func",
+            Value::Extern(_) => "extern",
+        }
+    }
+}
+
+impl<ID: std::fmt::Debug> std::fmt::Debug for Value<ID> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         match self {
             Value::Uninit => write!(f, "???"),
+            Value::Unit => write!(f, "()"),
+            Value::Bool(v) => write!(f, "{}", v),
             Value::I64(v) => write!(f, "{}_i64", v),
+            Value::F64(v) => write!(f, "{:?}_f64", v),
+            Value::Str(v) => write!(f, "{:?}", v),
+            Value::Func(closure) => write!(f, "func#{:?}", closure.code),
             Value::Extern(imp) => write!(f, "extern#{}", imp.name),
         }
     }
 }
 
+// How values are shown by `print`.
+impl<ID: std::fmt::Debug> std::fmt::Display for Value<ID> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Value::I64(v) => write!(f, "{}", v),
+            Value::F64(v) => write!(f, "{:?}", v),
+            Value::Str(v) => write!(f, "{}", v),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
 pub type MemIndex<ID> = TypedIndex<Value<ID>>;
 
 #[derive(Debug)]
 pub enum FnPtr<ID> {
     StaticPtr(ID),
     MemPtr(MemIndex<ID>),
+    Select(ID, MemIndex<ID>), // pick a branch of the conditional based on the evaluated condition.
+    Release(MemIndex<ID>),    // free the memory from this index, once the result has been written.
+    Unbind,  // remove the frame's bindings, once the frame that bound them is done.
+    Restore, // return to the caller's bindings, once a closure's body is done.
 }
 pub use FnPtr::*;
 
@@ -81,6 +148,18 @@ fn state_to_string<C: CompilerContext>(
         MemPtr(index) => {
             format!("{:?}{} -> {:?}", index, owning, state.get_mem(index))
         }
+        Select(ptr, index) => {
+            format!(
+                "{:?}{} -> select {:?} in {}",
+                ptr,
+                owning,
+                state.get_mem(index),
+                context.pretty(ptr)
+            )
+        }
+        Release(index) => format!("release from {:?}", index),
+        Unbind => format!("unbind {:?}", target.bindings),
+        Restore => "restore the caller's bindings".to_string(),
     }
 }
 
@@ -94,58 +173,256 @@ impl<ID: std::fmt::Debug> std::fmt::Debug for StackFrame<ID> {
     }
 }
 
+// Which of the `Limits` was exceeded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Steps(usize),
+    Memory(usize),
+    Depth(usize),
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Limit::Steps(max) => write!(f, "{} steps", max),
+            Limit::Memory(max) => write!(f, "{} values in memory", max),
+            Limit::Depth(max) => write!(f, "{} stack frames", max),
+        }
+    }
+}
+
+// A budget for evaluation (e.g. for running untrusted programs), unlimited by default.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    max_steps: Option<usize>,
+    max_mem: Option<usize>, // the length of the `mem_stack` (including externs).
+    max_depth: Option<usize>, // the length of the `function_stack`.
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Limits {
+    pub fn max_steps(self, max_steps: usize) -> Self {
+        Self {
+            max_steps: Some(max_steps),
+            ..self
+        }
+    }
+
+    pub fn max_mem(self, max_mem: usize) -> Self {
+        Self {
+            max_mem: Some(max_mem),
+            ..self
+        }
+    }
+
+    pub fn max_depth(self, max_depth: usize) -> Self {
+        Self {
+            max_depth: Some(max_depth),
+            ..self
+        }
+    }
+
+    // Stop evaluating once the flag is set (e.g. from another thread).
+    pub fn cancel_on(self, cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            cancel: Some(cancel),
+            ..self
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct EvalState<ID> {
     pub function_stack: Vec<StackFrame<ID>>, // name -> memory address to store result.
     // Record all the bindings (i.e. name->index in memory stack).
     pub bindings: HashMap<String, Vec<MemIndex<ID>>>, // name -> memory address to load result.
-    pub mem_stack: Vec<Value<ID>>,                    // results.
+    pub scopes: Vec<HashMap<String, Vec<MemIndex<ID>>>>, // the callers' bindings, hidden from closures.
+    free_names: HashMap<ID, BTreeSet<String>>, // lambda -> the names its closures capture.
+    pub mem_stack: Vec<Value<ID>>,             // results.
+    pub peak_mem_stack: usize,                 // the most memory used at once.
+    pub steps: usize,                          // frames evaluated so far.
+    pub limits: Limits,
+    pub io: Box<dyn Io>, // used by externs like `putchar` and `getchar`.
+    pub arithmetic: Arithmetic,
 }
 
-impl<ID: std::fmt::Debug> EvalState<ID> {
-    fn register_extern(mut self, imp: Impl<ID>) -> Self {
-        // add binding
-        let name = imp.name;
-        let index = self.alloc(Value::Extern(imp));
-        self.bind_name(name, index);
+// The externs that programs can call (e.g. `+` and `putchar`), which embedders can extend,
+// and the input and output that they use (captured by default).
+#[derive(Debug)]
+pub struct Environment<ID> {
+    externs: Vec<Impl<ID>>,
+    io: Box<dyn Io>,
+    arithmetic: Arithmetic,
+}
+
+impl<ID> Environment<ID> {
+    // An environment without any externs (not even the operators).
+    pub fn empty() -> Self {
+        Self {
+            externs: Vec::new(),
+            io: Box::<BufferIo>::default(),
+            arithmetic: Arithmetic::default(),
+        }
+    }
+
+    pub fn with_arithmetic(self, arithmetic: Arithmetic) -> Self {
+        Self { arithmetic, ..self }
+    }
+
+    pub fn with_io<I: Io + 'static>(self, io: I) -> Self {
+        Self {
+            io: Box::new(io),
+            ..self
+        }
+    }
+
+    // Later externs shadow earlier ones with the same name.
+    pub(crate) fn with_extern(mut self, imp: Impl<ID>) -> Self {
+        self.externs.push(imp);
         self
     }
-    fn run_extern(&mut self, imp: Impl<ID>) -> Result<Value<ID>, SteelErr> {
-        // Get the Arc<Mutex<ImpFn>>
-        let imp = imp.imp.clone();
-        let mut imp = imp.lock().unwrap(); // Get the ImpFn.
-        imp(self) // Run it
+
+    // Whether `name` is bound to a builtin (rather than missing or overridden by the embedder).
+    pub(crate) fn is_builtin(&self, name: &str) -> bool {
+        let imp = self.externs.iter().rev().find(|imp| imp.name == name);
+        imp.is_some_and(|imp| imp.builtin)
     }
 }
 
-impl<ID: Clone + std::fmt::Debug> Default for EvalState<ID> {
-    fn default() -> Self {
-        Self {
+impl<ID: std::fmt::Debug> EvalState<ID> {
+    pub fn new(environment: Environment<ID>) -> Self {
+        let mut state = Self {
             function_stack: Vec::new(),
             bindings: HashMap::new(),
+            scopes: Vec::new(),
+            free_names: HashMap::new(),
             mem_stack: Vec::new(),
+            peak_mem_stack: 0,
+            steps: 0,
+            limits: Limits::default(),
+            io: environment.io,
+            arithmetic: environment.arithmetic,
+        };
+        for imp in environment.externs {
+            let name = imp.name.clone();
+            let index = state.alloc(Value::Extern(imp));
+            state.bind_name(&name, index);
         }
-        .register_extern(Impl::new("+", |state| {
-            bin_op(state, "+", |l, r| l.wrapping_add(r))
-        }))
-        .register_extern(Impl::new("-", |state| {
-            bin_op(state, "-", |l, r| l.wrapping_sub(r))
-        }))
-        .register_extern(Impl::new("*", |state| {
-            bin_op(state, "*", |l, r| l.wrapping_mul(r))
-        }))
-        .register_extern(Impl::new("/", |state| {
-            bin_op(state, "/", |l, r| if r != 0 { l / r } else { 0 }) // TODO: Error values (/0)
-        }))
-        .register_extern(Impl::new("putchar", |state: &mut EvalState<ID>| {
-            if let Some(Value::I64(i)) = state.get_value_for("arg_0")? {
-                if let Some(c) = char::from_u32(*i as u32) {
-                    print!("{}", c);
+        state
+    }
+
+    fn run_extern(&mut self, imp: Impl<ID>, args: &Args<ID>) -> Result<Value<ID>, SteelErr> {
+        // Get the Arc<Mutex<ImpFn>>
+        let name = imp.name;
+        let imp = imp.imp.clone();
+        // Get the ImpFn (which can't be trusted to be in a usable state if it panicked before).
+        let mut imp = imp
+            .lock()
+            .map_err(|_| SteelErr::HostError(format!("{} panicked on an earlier call", name)))?;
+        imp(self, args) // Run it
+    }
+}
+
+impl<ID: Clone + std::fmt::Debug> Default for EvalState<ID> {
+    fn default() -> Self {
+        Self::new(Environment::default())
+    }
+}
+
+impl<ID: Clone + std::fmt::Debug> Default for Environment<ID> {
+    fn default() -> Self {
+        Self::empty()
+            .with_extern(Impl::new("+", |state| bin_op(state, Add, |l, r| l + r)))
+            .with_extern(Impl::new("-", |state| bin_op(state, Sub, |l, r| l - r)))
+            .with_extern(Impl::new("*", |state| bin_op(state, Mul, |l, r| l * r)))
+            .with_extern(Impl::new("/", |state| bin_op(state, Div, |l, r| l / r)))
+            .with_extern(Impl::new("==", |state| eq_op(state, "==", |eq| eq)))
+            .with_extern(Impl::new("!=", |state| eq_op(state, "!=", |eq| !eq)))
+            .with_extern(Impl::new("<", |state| {
+                cmp_op(state, "<", |ord| ord == Some(Less))
+            }))
+            .with_extern(Impl::new("<=", |state| {
+                cmp_op(state, "<=", |ord| matches!(ord, Some(Less | Equal)))
+            }))
+            .with_extern(Impl::new(">", |state| {
+                cmp_op(state, ">", |ord| ord == Some(Greater))
+            }))
+            .with_extern(Impl::new(">=", |state| {
+                cmp_op(state, ">=", |ord| matches!(ord, Some(Greater | Equal)))
+            }))
+            .with_extern(Impl::new("&&", |state| {
+                let l = get_bool(state, "&&", "arg_0")?;
+                let r = get_bool(state, "&&", "arg_1")?;
+                Ok(Value::Bool(l && r))
+            }))
+            .with_extern(Impl::new("||", |state| {
+                let l = get_bool(state, "||", "arg_0")?;
+                let r = get_bool(state, "||", "arg_1")?;
+                Ok(Value::Bool(l || r))
+            }))
+            .with_extern(Impl::new("!", |state| {
+                Ok(Value::Bool(!get_bool(state, "!", "arg_0")?))
+            }))
+            .with_extern(Impl::new("neg", |state: &mut EvalState<ID>| match get_arg(
+                state, "neg", "arg_0",
+            )? {
+                Value::I64(v) => Ok(Value::I64(state.arithmetic.negate(v)?)),
+                Value::F64(v) => Ok(Value::F64(-v)),
+                other => Err(type_error("neg", "arg_0", "a number", &other)),
+            }))
+            .with_extern(Impl::new("~", |state: &mut EvalState<ID>| {
+                match get_arg(state, "~", "arg_0")? {
+                    Value::I64(v) => Ok(Value::I64(!v)),
+                    other => Err(type_error("~", "arg_0", "i64", &other)),
+                }
+            }))
+            .with_extern(Impl::new("putchar", |state: &mut EvalState<ID>| {
+                let i = match state.get_value_for("arg_0")? {
+                    Some(Value::I64(i)) => *i,
+                    Some(other) => return Err(type_error("putchar", "arg_0", "i64", other)),
+                    None => return Ok(Value::I64(0)), // Nothing to print
+                };
+                if let Some(c) = char::from_u32(i as u32) {
+                    state.io.write(&c.to_string())?;
                     return Ok(Value::I64(1));
                 }
-            }
-            Ok(Value::I64(0)) // Could not print the unexpected value
-        }))
+                Ok(Value::I64(0)) // Could not print the unexpected value
+            }))
+            .with_extern(Impl::new("getchar", |state: &mut EvalState<ID>| {
+                match state.io.read_char()? {
+                    Some(c) => Ok(Value::I64(c as i64)),
+                    None => Ok(Value::I64(-1)), // The end of the input.
+                }
+            }))
+            .with_extern(Impl::new(
+                "to_f64",
+                |state: &mut EvalState<ID>| match get_arg(state, "to_f64", "arg_0")? {
+                    Value::I64(v) => Ok(Value::F64(v as f64)),
+                    Value::F64(v) => Ok(Value::F64(v)),
+                    other => Err(type_error("to_f64", "arg_0", "a number", &other)),
+                },
+            ))
+            .with_extern(Impl::new("to_i64", |state: &mut EvalState<ID>| {
+                match get_arg(state, "to_i64", "arg_0")? {
+                    Value::I64(v) => Ok(Value::I64(v)),
+                    Value::F64(v) => Ok(Value::I64(v as i64)), // Truncates (and saturates).
+                    other => Err(type_error("to_i64", "arg_0", "a number", &other)),
+                }
+            }))
+            .with_extern(Impl::new("print", |state: &mut EvalState<ID>| {
+                let value = get_arg(state, "print", "arg_0")?;
+                state.io.write(&format!("{}", value))?;
+                Ok(Value::Unit)
+            }))
+            .with_extern(Impl::new("concat", |state: &mut EvalState<ID>| {
+                let l = get_str(state, "concat", "arg_0")?;
+                let r = get_str(state, "concat", "arg_1")?;
+                Ok(Value::Str(l + &r))
+            }))
+            .with_extern(Impl::new("len", |state: &mut EvalState<ID>| {
+                let s = get_str(state, "len", "arg_0")?;
+                Ok(Value::I64(s.chars().count() as i64))
+            }))
     }
 }
 impl<ID> EvalState<ID> {
@@ -161,32 +438,111 @@ impl<ID> EvalState<ID> {
         Ok(r)
     }
 
-    fn get_mem(&self, index: MemIndex<ID>) -> Result<&Value<ID>, SteelErr> {
+    pub(crate) fn get_mem(&self, index: MemIndex<ID>) -> Result<&Value<ID>, SteelErr> {
         self.try_get_mem(index)?
             .ok_or(SteelErr::ReliedOnOutOfBoundsMemory(index.id))
     }
 
-    #[allow(unused)] // TODO: Implement freeing of memory
-    fn drop_mem(&mut self, mem: MemIndex<ID>) {
-        debug!("forgetting {:?} args", mem);
-        let final_length = self.mem_stack.len().saturating_sub(mem.id);
-        self.mem_stack.truncate(final_length);
+    // Free the memory from `mark` onwards, except for what the result still refers to (which is
+    // moved down to `mark`).
+    fn drop_mem(&mut self, mark: MemIndex<ID>, result: MemIndex<ID>) -> Result<(), SteelErr> {
+        let mut live = BTreeSet::new();
+        let mut todo = vec![result];
+        while let Some(index) = todo.pop() {
+            if let Some(Value::Func(closure)) = self.mem_stack.get(index.id) {
+                for (_name, captured) in &closure.captured {
+                    if captured.id >= mark.id && live.insert(captured.id) {
+                        todo.push(*captured);
+                    }
+                }
+            }
+        }
+        debug!("forgetting memory from {:?} (keeping {:?})", mark, live);
+        let moved: HashMap<usize, usize> = live
+            .iter()
+            .enumerate()
+            .map(|(offset, index)| (*index, mark.id + offset))
+            .collect();
+        let kept: Vec<Value<ID>> = live
+            .iter()
+            .map(|index| std::mem::replace(&mut self.mem_stack[*index], Value::Uninit))
+            .collect();
+        self.mem_stack.truncate(mark.id);
+        self.mem_stack.extend(kept);
+        let relocate = |value: &mut Value<ID>| {
+            if let Value::Func(closure) = value {
+                for (_name, captured) in &mut closure.captured {
+                    if let Some(index) = moved.get(&captured.id) {
+                        *captured = MemIndex::new(*index);
+                    }
+                }
+            }
+        };
+        for value in &mut self.mem_stack[mark.id..] {
+            relocate(value);
+        }
+        if let Some(value) = self.mem_stack.get_mut(result.id) {
+            relocate(value);
+        }
+        // Bindings don't need relocating: the frames that bound memory from the mark on have
+        // already been unbound (their Unbind frames run before this Release), and the callers'
+        // bindings stashed in `scopes` were all made before the mark was set.
+        Ok(())
+    }
+
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+
+    // Called before each step (so that evaluation can resume once the limits are raised).
+    fn check_limits(&self) -> Result<(), SteelErr> {
+        let limits = &self.limits;
+        let cancel = limits.cancel.as_ref();
+        if cancel.is_some_and(|cancel| cancel.load(std::sync::atomic::Ordering::Relaxed)) {
+            return Err(SteelErr::Cancelled);
+        }
+        let exceeded = |max: Option<usize>, used: usize| max.filter(|max| used > *max);
+        let limit = if let Some(max) = exceeded(limits.max_steps, self.steps + 1) {
+            Limit::Steps(max)
+        } else if let Some(max) = exceeded(limits.max_mem, self.mem_stack.len()) {
+            Limit::Memory(max)
+        } else if let Some(max) = exceeded(limits.max_depth, self.function_stack.len()) {
+            Limit::Depth(max)
+        } else {
+            return Ok(());
+        };
+        Err(SteelErr::ResourceLimitExceeded(limit))
     }
 
     fn alloc(&mut self, value: Value<ID>) -> MemIndex<ID> {
         let index = self.mem_stack.len();
         self.mem_stack.push(value);
+        self.peak_mem_stack = self.peak_mem_stack.max(self.mem_stack.len());
         MemIndex::new(index)
     }
 
+    // Run after everything set up later, so that memory allocated from here on can be freed.
+    fn setup_release(&mut self, return_address: MemIndex<ID>) {
+        let mark = MemIndex::new(self.mem_stack.len());
+        self.setup_eval_to(FnPtr::Release(mark), return_address, Vec::new());
+    }
+
     pub fn bind_name(&mut self, name: &str, index: MemIndex<ID>) {
-        let entries = self
-            .bindings
-            .entry(name.to_string())
-            .or_insert_with(Vec::new);
+        let entries = self.bindings.entry(name.to_string()).or_default();
         entries.push(index); // Vec allows shadowing
     }
 
+    pub fn unbind_name(&mut self, name: &str, index: MemIndex<ID>) {
+        if let Some(entries) = self.bindings.get_mut(name) {
+            if let Some(position) = entries.iter().rposition(|entry| *entry == index) {
+                entries.remove(position); // Reveal any shadowed binding.
+            }
+            if entries.is_empty() {
+                self.bindings.remove(name);
+            }
+        }
+    }
+
     pub fn setup_eval_to(
         &mut self,
         fn_ptr: FnPtr<ID>,
@@ -204,13 +560,18 @@ impl<ID> EvalState<ID> {
         &mut self,
         code: ID,
         return_address: MemIndex<ID>,
-        mut bindings: Vec<(String, MemIndex<ID>)>,
+        bindings: Vec<(String, MemIndex<ID>)>,
     ) -> MemIndex<ID> {
-        let callee_index = self.alloc(Value::Uninit); // explicitly store 'uninitialized' marker.
-                                                      // then run the closure
-        bindings.push(("self".to_string(), callee_index));
-        self.setup_eval_to(FnPtr::MemPtr(callee_index), return_address, Vec::new());
-        // but first fetch the 'code'.
+        // explicitly store 'uninitialized' marker.
+        let callee_index = self.alloc(Value::Uninit);
+        // then run the closure
+        // The arguments are also needed to run the closure (e.g. to bind parameters).
+        self.setup_eval_to(
+            FnPtr::MemPtr(callee_index),
+            return_address,
+            bindings.clone(),
+        );
+        // but first fetch the 'code' (where `self` is still the enclosing function).
         self.setup_eval_to(FnPtr::StaticPtr(code), callee_index, bindings);
         return_address
     }
@@ -225,15 +586,37 @@ impl<ID> EvalState<ID> {
         return_address
     }
 
+    // Record the current (innermost) binding for each of the lambda's free names.
+    fn capture<C: CompilerContext<ID = ID>>(
+        &mut self,
+        context: &C,
+        lambda: ID,
+    ) -> Vec<(String, MemIndex<ID>)>
+    where
+        ID: Eq + std::hash::Hash + Copy,
+    {
+        // Lambdas are evaluated repeatedly (e.g. in loops), so only walk each body once.
+        let names = self
+            .free_names
+            .entry(lambda)
+            .or_insert_with(|| free_names(context, lambda));
+        names
+            .iter()
+            .filter_map(|name| Some((name.to_string(), *self.bindings.get(name)?.last()?)))
+            .collect()
+    }
+
+    // The value of the innermost binding of the name, if it is bound.
     pub fn get_value_for(&mut self, name: &str) -> Result<Option<&Value<ID>>, SteelErr> {
-        let mut bindings = self.bindings.get(name).cloned().unwrap_or_default();
-        while let Some(binding) = bindings.last() {
-            if let Some(value) = self.try_get_mem(*binding)? {
-                return Ok(Some(value));
-            }
-            bindings.pop();
+        let binding = if let Some(binding) = self.bindings.get(name).and_then(|b| b.last()) {
+            *binding
+        } else {
+            return Ok(None);
+        };
+        match self.try_get_mem(binding)? {
+            Some(value) => Ok(Some(value)),
+            None => Err(SteelErr::MissingValueForBinding(name.to_string())),
         }
-        Ok(None)
     }
 }
 
@@ -241,11 +624,28 @@ pub fn eval<C: CompilerContext>(context: &C, state: &mut EvalState<C::ID>) -> Re
 where
     <C as CompilerContext>::E: Into<SteelErr>,
 {
-    while let Some(target) = state.function_stack.pop() {
+    while step_next(context, state)? {}
+    Ok(())
+}
+
+// Run the next frame on the stack, returning false once there is nothing left to run.
+pub fn step_next<C: CompilerContext>(
+    context: &C,
+    state: &mut EvalState<C::ID>,
+) -> Result<bool, SteelErr>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    if state.function_stack.is_empty() {
+        return Ok(false);
+    }
+    state.check_limits()?;
+    if let Some(target) = state.function_stack.pop() {
+        state.steps += 1;
         debug!("evaluating: {}", state_to_string(context, state, &target));
         step(context, state, target)?;
     }
-    Ok(())
+    Ok(true)
 }
 
 pub fn step<C: CompilerContext>(
@@ -257,10 +657,13 @@ where
     <C as CompilerContext>::E: Into<SteelErr>,
 {
     trace!("state: {:?}", state.mem_stack);
-    perform(context, state, &target)?;
-    // if target.bindings > 0 {
-    // state.drop_mem(target.bindings);
-    // }
+    perform(context, state, &target).map_err(|err| match target.fn_ptr {
+        StaticPtr(id) | Select(id, _) => match context.get_span(id) {
+            Ok(span) => err.at(*span),
+            Err(_) => err,
+        },
+        MemPtr(_) | Release(_) | Unbind | Restore => err,
+    })?;
     Ok(())
 }
 
@@ -277,24 +680,83 @@ where
         return_address,
         bindings,
     } = target;
+    if let Unbind = fn_ptr {
+        for (name, index) in bindings {
+            state.unbind_name(name, *index);
+        }
+        return Ok(());
+    }
+    if let Restore = fn_ptr {
+        if let Some(caller) = state.scopes.pop() {
+            state.bindings = caller;
+        }
+        return Ok(());
+    }
     for (name, index) in bindings {
         state.bind_name(name, *index);
     }
+    if !bindings.is_empty() {
+        // Runs after everything this frame sets up.
+        state.setup_eval_to(FnPtr::Unbind, *return_address, bindings.clone());
+    }
     let id = match fn_ptr {
         MemPtr(index) => {
             let func = state.get_mem(*index)?.clone();
             // should drop the closure.
             trace!("running closure {:?} {:?}", func, target.bindings);
             let res = match func {
-                Value::Extern(imp) => state.run_extern(imp)?,
+                Value::Extern(imp) => state.run_extern(imp, &target.bindings)?,
+                Value::Func(closure) => {
+                    let lambda = context.get_lambda(closure.code).map_err(Into::into)?;
+                    let mut bindings = closure.captured;
+                    for (arg_num, param) in lambda.params.iter().enumerate() {
+                        let positional = format!("arg_{}", arg_num);
+                        let arg = target
+                            .bindings
+                            .iter()
+                            .find(|(name, _index)| name == param)
+                            .or_else(|| {
+                                target
+                                    .bindings
+                                    .iter()
+                                    .find(|(name, _index)| name == &positional)
+                            });
+                        let (_name, arg) =
+                            arg.ok_or_else(|| SteelErr::MissingArgument(param.to_string()))?;
+                        bindings.push((param.to_string(), *arg));
+                    }
+                    // Allow recursion.
+                    bindings.push(("self".to_string(), *index));
+                    // The body only sees these bindings (not the caller's) until it is done.
+                    let caller = std::mem::take(&mut state.bindings);
+                    state.scopes.push(caller);
+                    state.setup_eval_to(FnPtr::Restore, *return_address, Vec::new());
+                    state.setup_eval_to(FnPtr::StaticPtr(lambda.body), *return_address, bindings);
+                    return Ok(()); // the body will write the result.
+                }
                 constant => constant,
             };
             state.set_mem(target.return_address, res);
             return Ok(()); // done!
         }
+        Select(id, condition) => {
+            let conditional = context.get_if(*id).map_err(Into::into)?;
+            let branch = match state.get_mem(*condition)? {
+                Value::Bool(true) => conditional.then,
+                Value::Bool(false) => conditional.otherwise,
+                value => return Err(SteelErr::InvalidCondition(format!("{:?}", value))),
+            };
+            // Only the taken branch is evaluated.
+            state.setup_eval_to(FnPtr::StaticPtr(branch), *return_address, Vec::new());
+            return Ok(());
+        }
+        Release(mark) => return state.drop_mem(*mark, *return_address),
+        // Unbind and Restore frames are handled before binding.
+        Unbind | Restore => return Err(SteelErr::UnexpectedFrame(format!("{:?}", fn_ptr))),
         StaticPtr(id) => *id,
     };
     if let Ok(c) = context.get_call(id) {
+        state.setup_release(*return_address);
         // load in all the args
         let mut args = vec![];
         let mut todos = vec![];
@@ -310,52 +772,399 @@ where
         }
         return Ok(());
     }
+    if context.get_lambda(id).is_ok() {
+        let closure = Closure {
+            code: id,
+            captured: state.capture(context, id),
+        };
+        state.set_mem(*return_address, Value::Func(closure));
+        return Ok(());
+    }
+    if let Ok(binding) = context.get_let(id) {
+        state.setup_release(*return_address);
+        let index = state.alloc(Value::Uninit);
+        // Evaluate the value first, then the body with the value bound to the name.
+        state.setup_eval_to(
+            FnPtr::StaticPtr(binding.body),
+            *return_address,
+            vec![(binding.name.to_string(), index)],
+        );
+        state.setup_eval_to(FnPtr::StaticPtr(binding.value), index, Vec::new());
+        return Ok(());
+    }
+    if let Ok(conditional) = context.get_if(id) {
+        state.setup_release(*return_address);
+        let index = state.alloc(Value::Uninit);
+        // Evaluate the condition first, then select a branch.
+        state.setup_eval_to(FnPtr::Select(id, index), *return_address, Vec::new());
+        state.setup_eval_to(FnPtr::StaticPtr(conditional.condition), index, Vec::new());
+        return Ok(());
+    }
     let res = if let Ok(v) = context.get_i64(id) {
         trace!("get constant i64 {}", v);
         Value::I64(*v)
+    } else if let Ok(v) = context.get_f64(id) {
+        trace!("get constant f64 {:?}", v);
+        Value::F64(*v)
+    } else if let Ok(v) = context.get_bool(id) {
+        trace!("get constant bool {}", v);
+        Value::Bool(*v)
+    } else if let Ok(s) = context.get_str(id) {
+        trace!("get constant str {:?}", s.value);
+        Value::Str(s.value.to_string())
     } else if let Ok(s) = context.get_operator(id) {
         trace!("get operator {:?}", &s);
         state
-            .get_value_for(s.to_str())?
+            .get_value_for(s.extern_name())?
             .cloned()
-            .ok_or_else(|| SteelErr::MissingValueForBinding(s.to_string()))?
+            .ok_or_else(|| SteelErr::UnboundName(s.extern_name().to_string()))?
     } else if let Ok(s) = context.get_symbol(id) {
         trace!("get symbol {:?}", &s.name);
         state
             .get_value_for(&s.name)?
             .cloned()
-            .ok_or_else(|| SteelErr::MissingValueForBinding(s.name.to_string()))?
+            .ok_or_else(|| SteelErr::UnboundName(s.name.to_string()))?
+    } else if let Ok(invalid) = context.get_invalid(id) {
+        return Err(SteelErr::EvaluatedInvalidNode(invalid.message.to_string()));
     } else {
         // format!("{{node? {:?}}}", id)
         error!("Unknown node {}, {:?}", context.pretty(id), id);
-        todo!("Unknown node {:?}", id);
+        return Err(SteelErr::UnknownNode(format!("{:?}", id)));
     };
     state.set_mem(target.return_address, res);
     Ok(())
 }
 
-fn bin_op<ID: Clone + std::fmt::Debug, F: FnOnce(i64, i64) -> i64>(
+// A frame waiting to be run, as seen from outside the interpreter.
+#[derive(Clone, Debug)]
+pub struct Frame<ID> {
+    pub node: Option<ID>,    // the code the frame evaluates (if any).
+    pub description: String, // what the frame will do.
+    pub return_address: MemIndex<ID>,
+    pub bindings: Vec<(String, MemIndex<ID>)>,
+}
+
+// A copy of the evaluation state between steps (e.g. for debuggers and visualisations).
+#[derive(Clone, Debug)]
+pub struct Snapshot<ID> {
+    pub steps: usize,
+    pub current: Option<String>, // the node that the next frame evaluates (pretty printed).
+    pub frames: Vec<Frame<ID>>,  // the function stack, with the next frame to run last.
+    pub bindings: BTreeMap<String, Value<ID>>, // the innermost binding of each name in scope (excluding externs).
+    pub memory: Vec<Value<ID>>,
+}
+
+impl<ID> Snapshot<ID> {
+    pub fn current_frame(&self) -> Option<&Frame<ID>> {
+        self.frames.last()
+    }
+}
+
+// Evaluates a program one frame at a time, so that it can be paused, inspected and resumed.
+pub struct Evaluator<'a, C: CompilerContext> {
+    context: &'a C,
+    state: EvalState<C::ID>,
+    result: MemIndex<C::ID>,
+    globals: usize, // the memory used by externs.
+}
+
+impl<'a, C: CompilerContext> Evaluator<'a, C>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    pub fn new(context: &'a C, expr: C::ID) -> Self {
+        Self::with_environment(context, expr, Environment::default())
+    }
+
+    pub fn with_environment(context: &'a C, expr: C::ID, environment: Environment<C::ID>) -> Self {
+        let mut state = EvalState::new(environment);
+        let globals = state.mem_stack.len();
+        let result = state.setup_eval(StaticPtr(expr), Vec::new());
+        Self {
+            context,
+            state,
+            result,
+            globals,
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.set_limits(limits);
+        self
+    }
+
+    // Limits can be raised to resume evaluation after they were exceeded.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.state.limits = limits;
+    }
+
+    pub fn state(&self) -> &EvalState<C::ID> {
+        &self.state
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state.function_stack.is_empty()
+    }
+
+    // Run one frame, returning false if there was nothing left to run.
+    // Errors (other than exceeded limits and cancellation) can't be resumed from.
+    pub fn step(&mut self) -> Result<bool, SteelErr> {
+        step_next(self.context, &mut self.state)
+    }
+
+    // Run up to `steps` frames, returning how many were run.
+    pub fn run_for(&mut self, steps: usize) -> Result<usize, SteelErr> {
+        for ran in 0..steps {
+            if !self.step()? {
+                return Ok(ran);
+            }
+        }
+        Ok(steps)
+    }
+
+    // Run until the predicate holds before a step, returning false if the program finished first.
+    pub fn run_until<P: FnMut(&Self) -> bool>(
+        &mut self,
+        mut predicate: P,
+    ) -> Result<bool, SteelErr> {
+        while !self.is_done() {
+            if predicate(self) {
+                return Ok(true);
+            }
+            self.step()?;
+        }
+        Ok(false)
+    }
+
+    pub fn run(&mut self) -> Result<(), SteelErr> {
+        eval(self.context, &mut self.state)
+    }
+
+    // Where the value of the program is written.
+    pub fn result_index(&self) -> MemIndex<C::ID> {
+        self.result
+    }
+
+    // What the program has written (if the environment captures output).
+    pub fn output(&self) -> &str {
+        self.state.io.output()
+    }
+
+    // The value of the program (once it has finished).
+    pub fn result(&self) -> Option<&Value<C::ID>> {
+        match self.state.mem_stack.get(self.result.id) {
+            Some(Value::Uninit) => None,
+            result => result,
+        }
+    }
+
+    // The number of function bodies that have been entered and not yet returned from.
+    pub fn call_depth(&self) -> usize {
+        let entered = |frame: &&StackFrame<C::ID>| {
+            let binds_self = frame.bindings.iter().any(|(name, _index)| name == "self");
+            matches!(frame.fn_ptr, Unbind) && binds_self
+        };
+        self.state.function_stack.iter().filter(entered).count()
+    }
+
+    // The node that the next frame evaluates (if any).
+    pub fn current_node(&self) -> Option<C::ID> {
+        self.state.function_stack.last().and_then(frame_node)
+    }
+
+    // Like `state_to_string`, but without the details that are only useful for debugging the interpreter.
+    fn describe(&self, frame: &StackFrame<C::ID>) -> String {
+        let value = |index: MemIndex<C::ID>| match self.state.mem_stack.get(index.id) {
+            Some(Value::Uninit) | None => "???".to_string(),
+            Some(value) => format!("{:?}", value),
+        };
+        match frame.fn_ptr {
+            StaticPtr(id) => self.context.pretty(id),
+            MemPtr(index) => format!("call {}", value(index)),
+            Select(id, index) => format!("select {} in {}", value(index), self.context.pretty(id)),
+            Release(mark) => format!("release from {:?}", mark),
+            Unbind => {
+                let names: Vec<&str> = frame
+                    .bindings
+                    .iter()
+                    .filter(|(_name, index)| index.id >= self.globals)
+                    .map(|(name, _index)| name.as_str())
+                    .collect();
+                format!("unbind {}", names.join(", "))
+            }
+            Restore => "restore the caller's bindings".to_string(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot<C::ID> {
+        let state = &self.state;
+        let frames = state
+            .function_stack
+            .iter()
+            .map(|frame| Frame {
+                node: frame_node(frame),
+                description: self.describe(frame),
+                return_address: frame.return_address,
+                bindings: frame.bindings.clone(),
+            })
+            .collect();
+        // The next frame's bindings are in scope for its node (but are only bound when it runs).
+        let pending = state
+            .function_stack
+            .last()
+            .filter(|frame| !matches!(frame.fn_ptr, Unbind))
+            .map(|frame| frame.bindings.clone())
+            .unwrap_or_default();
+        let bindings = state
+            .bindings
+            .iter()
+            .filter_map(|(name, indexes)| Some((name, *indexes.last()?)))
+            .chain(pending.iter().map(|(name, index)| (name, *index)))
+            .filter(|(_name, index)| index.id >= self.globals)
+            .filter_map(|(name, index)| {
+                let value = state.mem_stack.get(index.id)?;
+                Some((name.to_string(), value.clone()))
+            })
+            .collect();
+        Snapshot {
+            steps: state.steps,
+            current: self.current_node().map(|id| self.context.pretty(id)),
+            frames,
+            bindings,
+            memory: state.mem_stack.clone(),
+        }
+    }
+}
+
+// The names that a lambda's body may look up, other than its params and `self` (which are bound
+// when it is called). Closures only capture these, so that they don't keep everything in scope alive.
+fn free_names<C: CompilerContext>(context: &C, lambda: C::ID) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    context.walk(lambda, &mut |context, id| {
+        if let Ok(symbol) = context.get_symbol(id) {
+            names.insert(symbol.name.to_string());
+        } else if let Ok(operator) = context.get_operator(id) {
+            names.insert(operator.extern_name().to_string());
+        }
+    });
+    if let Ok(lambda) = context.get_lambda(lambda) {
+        for param in &lambda.params {
+            names.remove(param);
+        }
+    }
+    names.remove("self");
+    names
+}
+
+fn frame_node<ID: Copy>(frame: &StackFrame<ID>) -> Option<ID> {
+    match frame.fn_ptr {
+        StaticPtr(id) | Select(id, _) => Some(id),
+        MemPtr(_) | Release(_) | Unbind | Restore => None,
+    }
+}
+
+fn get_arg<ID: Clone + std::fmt::Debug>(
+    state: &mut EvalState<ID>,
+    func: &str,
+    arg: &str,
+) -> Result<Value<ID>, SteelErr> {
+    state
+        .get_value_for(arg)?
+        .cloned()
+        .ok_or_else(|| SteelErr::MissingArgumentExpectedByExtern(func.to_string(), arg.to_string()))
+}
+
+pub(crate) fn type_error<ID: std::fmt::Debug>(
+    func: &str,
+    arg: &str,
+    expected: &str,
+    found: &Value<ID>,
+) -> SteelErr {
+    SteelErr::TypeError {
+        func: func.to_string(),
+        arg: arg.to_string(),
+        expected: expected.to_string(),
+        found: format!("{:?}", found),
+    }
+}
+
+fn get_bool<ID: Clone + std::fmt::Debug>(
+    state: &mut EvalState<ID>,
+    func: &str,
+    arg: &str,
+) -> Result<bool, SteelErr> {
+    match get_arg(state, func, arg)? {
+        Value::Bool(v) => Ok(v),
+        other => Err(type_error(func, arg, "bool", &other)),
+    }
+}
+
+fn get_str<ID: Clone + std::fmt::Debug>(
+    state: &mut EvalState<ID>,
+    func: &str,
+    arg: &str,
+) -> Result<String, SteelErr> {
+    match get_arg(state, func, arg)? {
+        Value::Str(v) => Ok(v),
+        other => Err(type_error(func, arg, "str", &other)),
+    }
+}
+
+// Arithmetic needs both arguments to be the same kind of number (see `to_f64` and `to_i64`).
+fn bin_op<ID, F>(
+    state: &mut EvalState<ID>,
+    op: Operator,
+    float_op: F,
+) -> Result<Value<ID>, SteelErr>
+where
+    ID: Clone + std::fmt::Debug,
+    F: FnOnce(f64, f64) -> f64,
+{
+    let name = op.to_str();
+    let l = get_arg(state, name, "arg_0")?;
+    let r = get_arg(state, name, "arg_1")?;
+    match (&l, &r) {
+        (Value::I64(l), Value::I64(r)) => Ok(Value::I64(state.arithmetic.binary(op, *l, *r)?)),
+        (Value::F64(l), Value::F64(r)) => Ok(Value::F64(float_op(*l, *r))),
+        (Value::I64(_) | Value::F64(_), _) => Err(type_error(name, "arg_1", l.kind(), &r)),
+        _ => Err(type_error(name, "arg_0", "a number", &l)),
+    }
+}
+
+fn cmp_op<ID, F>(state: &mut EvalState<ID>, name: &str, op: F) -> Result<Value<ID>, SteelErr>
+where
+    ID: Clone + std::fmt::Debug,
+    F: FnOnce(Option<Ordering>) -> bool,
+{
+    let l = get_arg(state, name, "arg_0")?;
+    let r = get_arg(state, name, "arg_1")?;
+    let ord = match (&l, &r) {
+        (Value::I64(l), Value::I64(r)) => l.partial_cmp(r),
+        (Value::F64(l), Value::F64(r)) => l.partial_cmp(r), // None for NaN.
+        (Value::I64(_) | Value::F64(_), _) => return Err(type_error(name, "arg_1", l.kind(), &r)),
+        _ => return Err(type_error(name, "arg_0", "a number", &l)),
+    };
+    Ok(Value::Bool(op(ord)))
+}
+
+// Values of the same kind can be compared for equality (functions can't be compared).
+fn eq_op<ID: Clone + std::fmt::Debug, F: FnOnce(bool) -> bool>(
     state: &mut EvalState<ID>,
     name: &str,
     op: F,
 ) -> Result<Value<ID>, SteelErr> {
-    let l = state.get_value_for("arg_0")?.cloned();
-    let l = if let Some(Value::I64(l)) = l {
-        l
-    } else {
-        return Err(SteelErr::MissingArgumentExpectedByExtern(
-            name.to_string(),
-            "arg_0".to_string(),
-        ));
-    };
-    let r = state.get_value_for("arg_1")?.cloned();
-    let r = if let Some(Value::I64(r)) = r {
-        r
-    } else {
-        return Err(SteelErr::MissingArgumentExpectedByExtern(
-            name.to_string(),
-            "arg_1".to_string(),
-        ));
+    let l = get_arg(state, name, "arg_0")?;
+    let r = get_arg(state, name, "arg_1")?;
+    let eq = match (&l, &r) {
+        (Value::Unit, Value::Unit) => true,
+        (Value::Bool(l), Value::Bool(r)) => l == r,
+        (Value::I64(l), Value::I64(r)) => l == r,
+        (Value::F64(l), Value::F64(r)) => l == r,
+        (Value::Str(l), Value::Str(r)) => l == r,
+        (Value::Unit | Value::Bool(_) | Value::I64(_) | Value::F64(_) | Value::Str(_), _) => {
+            return Err(type_error(name, "arg_1", l.kind(), &r))
+        }
+        _ => return Err(type_error(name, "arg_0", "a comparable value", &l)),
     };
-    Ok(Value::I64(op(l, r)))
+    Ok(Value::Bool(op(eq)))
 }
@@ -0,0 +1,83 @@
+use crate::error::SteelErr;
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+
+// Where externs like `putchar` and `getchar` send and receive text.
+pub trait Io: std::fmt::Debug {
+    fn write(&mut self, text: &str) -> Result<(), SteelErr>;
+    fn read_char(&mut self) -> Result<Option<char>, SteelErr>; // None at the end of the input.
+
+    // What has been written so far (if it was captured).
+    fn output(&self) -> &str {
+        ""
+    }
+}
+
+// The process's stdin and stdout.
+#[derive(Debug, Default)]
+pub struct StdIo {
+    pending: VecDeque<char>, // the rest of the line that was last read.
+}
+
+impl Io for StdIo {
+    fn write(&mut self, text: &str) -> Result<(), SteelErr> {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn read_char(&mut self) -> Result<Option<char>, SteelErr> {
+        if self.pending.is_empty() {
+            let mut line = String::new();
+            std::io::stdin().lock().read_line(&mut line)?;
+            self.pending.extend(line.chars());
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+// Reads from a fixed input and captures the output (e.g. for tests).
+#[derive(Debug, Default)]
+pub struct BufferIo {
+    input: VecDeque<char>,
+    output: String,
+}
+
+impl BufferIo {
+    pub fn with_input(input: &str) -> Self {
+        Self {
+            input: input.chars().collect(),
+            output: String::new(),
+        }
+    }
+}
+
+impl Io for BufferIo {
+    fn write(&mut self, text: &str) -> Result<(), SteelErr> {
+        self.output += text;
+        Ok(())
+    }
+
+    fn read_char(&mut self) -> Result<Option<char>, SteelErr> {
+        Ok(self.input.pop_front())
+    }
+
+    fn output(&self) -> &str {
+        &self.output
+    }
+}
+
+// Discards the output and has no input.
+#[derive(Debug, Default)]
+pub struct NullIo;
+
+impl Io for NullIo {
+    fn write(&mut self, _text: &str) -> Result<(), SteelErr> {
+        Ok(())
+    }
+
+    fn read_char(&mut self) -> Result<Option<char>, SteelErr> {
+        Ok(None)
+    }
+}
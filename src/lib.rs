@@ -1,29 +1,40 @@
 // TODO: Remove when we can run in ECS and AST mode.
 
-mod compact_arena; // Boiler plate: should be a dependency.
+#[cfg(test)]
+#[macro_use]
+mod assertions;
+pub mod arithmetic;
 pub mod ast;
+mod compact_arena; // Boiler plate: should be a dependency.
 mod compiler_context;
+pub mod debugger;
+pub mod diagnostics;
+pub mod differential;
 pub mod ecs;
 mod error;
+pub mod fuzz;
 pub mod gen_code;
+pub mod host;
 mod interpreter;
+pub mod io;
 pub mod nodes;
+pub mod operators;
 mod optimizer;
 mod parser;
 mod pretty_printer;
+pub mod shrink;
 mod tombstoning_arena; // Boiler plate: should be a dependency.
 pub mod typed_index;
 
-#[cfg(test)]
-#[macro_use]
-mod assertions;
 #[cfg(test)]
 mod integration_tests;
 
 pub use crate::compiler_context::CompilerContext;
 pub use crate::error::SteelErr;
-use crate::interpreter::{eval, EvalState, StaticPtr, Value};
-use crate::parser::program;
+pub use crate::interpreter::{
+    Environment, Evaluator, Frame, Limit, Limits, MemIndex, Snapshot, Value,
+};
+pub use crate::parser::{program_recovering, program_with_operators, program_with_trivia};
 use log::{debug, error};
 
 #[derive(Debug, Default)]
@@ -43,6 +54,10 @@ pub struct Tasks<'a, ID> {
     optimize: optimizer::Optimizations,
     print_optimized: bool,
     eval: bool,
+    operators: operators::OperatorTable,
+    limits: Limits,
+    environment: Option<Environment<ID>>, // the default externs if not set.
+    arithmetic: arithmetic::Arithmetic,   // used by both the optimizer and the interpreter.
 }
 
 impl<'a, ID> Default for Tasks<'a, ID> {
@@ -53,6 +68,10 @@ impl<'a, ID> Default for Tasks<'a, ID> {
             optimize: optimizer::Optimizations::none(),
             print_optimized: false,
             eval: false,
+            operators: operators::OperatorTable::default(),
+            limits: Limits::default(),
+            environment: None,
+            arithmetic: arithmetic::Arithmetic::default(),
         }
     }
 }
@@ -91,6 +110,21 @@ impl<'a, ID> Tasks<'a, ID> {
     pub fn and_eval(self) -> Self {
         Self { eval: true, ..self }
     }
+    pub fn and_operators(self, operators: operators::OperatorTable) -> Self {
+        Self { operators, ..self }
+    }
+    pub fn and_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+    pub fn and_arithmetic(self, arithmetic: arithmetic::Arithmetic) -> Self {
+        Self { arithmetic, ..self }
+    }
+    pub fn and_environment(self, environment: Environment<ID>) -> Self {
+        Self {
+            environment: Some(environment),
+            ..self
+        }
+    }
     pub fn all(program: &'a str) -> Self {
         Self::parse(program)
             .and_print()
@@ -114,7 +148,7 @@ where
     env_logger::init();
     let mut args = std::env::args();
     let _program_path = args.next();
-    for arg in args {
+    if let Some(arg) = args.next() {
         error!("unknown argument: {}", arg);
         std::process::exit(1);
     }
@@ -128,13 +162,26 @@ where
         debug!("{:?}", tasks);
         let store = handle::<Ctx>(tasks);
         debug!("{}: {:?}", name, store);
-        println!("{:?}", store);
+        match store {
+            Ok((expr, res, output)) => {
+                // Keep the program's output separate from the result.
+                print!("{}", output);
+                if !output.is_empty() && !output.ends_with('\n') {
+                    println!();
+                }
+                println!("{:?}", (expr, res));
+            }
+            Err(err) => {
+                let style = diagnostics::Style::for_stderr();
+                eprint!("{}", diagnostics::render(&err, &line, "<stdin>", style));
+            }
+        }
     }
 }
 
 pub fn handle<Ctx: CompilerContext>(
     steps: Tasks<Ctx::ID>,
-) -> Result<(Option<Ctx::ID>, i64), SteelErr>
+) -> Result<(Option<Ctx::ID>, i64, String), SteelErr>
 where
     SteelErr: From<<Ctx as CompilerContext>::E>,
 {
@@ -145,56 +192,95 @@ where
 pub fn handle_steps<Ctx: CompilerContext>(
     store: &mut Ctx,
     steps: Tasks<Ctx::ID>,
-) -> Result<(Option<Ctx::ID>, i64), SteelErr>
+) -> Result<(Option<Ctx::ID>, i64, String), SteelErr>
 where
     SteelErr: From<<Ctx as CompilerContext>::E>,
 {
     let (program_txt, expr) = match steps.program {
-        Nothing => return Ok((None, 0)),
+        Nothing => return Ok((None, 0, String::new())),
         FromStr(program_txt) => {
-            let (_input, expr) = program(store, program_txt)?;
+            let (_input, expr) = program_with_operators(store, program_txt, &steps.operators)?;
             (program_txt.to_string(), expr)
         }
-        FromStore(expr) => (store.pretty(expr), expr),
+        FromStore(expr) => (store.pretty_with(expr, &steps.operators), expr),
     };
-    debug!("expr: {:?}", store.pretty(expr));
+    debug!("expr: {:?}", store.pretty_with(expr, &steps.operators));
     if steps.print {
-        eprintln!("{}", store.pretty(expr));
+        eprintln!("{}", store.pretty_with(expr, &steps.operators));
     }
+    let environment = steps
+        .environment
+        .unwrap_or_default()
+        .with_arithmetic(steps.arithmetic);
     let expr = if steps.optimize != optimizer::Optimizations::none() {
-        store.optimize(&steps.optimize, expr)?
+        // Only operators that evaluate to the builtins can be evaluated ahead of time.
+        let overridden = nodes::Operator::all()
+            .iter()
+            .filter(|op| !environment.is_builtin(op.extern_name()));
+        let optimizations = steps
+            .optimize
+            .with_arithmetic(steps.arithmetic)
+            .without_folding(overridden.copied());
+        store.optimize(&optimizations, expr)?
     } else {
         expr
     };
     if steps.print_optimized {
-        eprintln!("{}", store.pretty(expr));
+        eprintln!("{}", store.pretty_with(expr, &steps.operators));
     }
     if steps.eval {
-        return Ok((Some(expr), eval_program(store, expr, &program_txt)?));
+        let (res, output) =
+            eval_program_with_environment(store, expr, &program_txt, environment, steps.limits)?;
+        return Ok((Some(expr), res, output));
     }
-    Ok((Some(expr), 0)) // TODO: Find a better result value
+    Ok((Some(expr), 0, String::new())) // TODO: Find a better result value
 }
 
 pub fn eval_program<Ctx: CompilerContext>(
     store: &mut Ctx,
     expr: Ctx::ID,
     program_txt: &str,
+) -> Result<(i64, String), SteelErr> {
+    eval_program_with_limits(store, expr, program_txt, Limits::default())
+}
+
+pub fn eval_program_with_limits<Ctx: CompilerContext>(
+    store: &mut Ctx,
+    expr: Ctx::ID,
+    program_txt: &str,
+    limits: Limits,
+) -> Result<(i64, String), SteelErr> {
+    let environment = Environment::default();
+    eval_program_with_environment(store, expr, program_txt, environment, limits)
+}
+
+pub fn eval_program_with_environment<Ctx: CompilerContext>(
+    store: &mut Ctx,
+    expr: Ctx::ID,
+    program_txt: &str,
+    environment: Environment<Ctx::ID>,
+    limits: Limits,
+) -> Result<(i64, String), SteelErr> {
+    let mut evaluator = Evaluator::with_environment(store, expr, environment).with_limits(limits);
+    evaluator.run()?;
+    let res = evaluator.state().mem_stack.get(evaluator.result_index().id);
+    debug!("eval: {:#?} {:#?}\n{}", evaluator.state(), res, program_txt);
+    let res = program_result(res, evaluator.result_index().id)?;
+    Ok((res, evaluator.output().to_string()))
+}
+
+// Programs evaluate to an i64, with bools and unit standing in for 1/0 and 0.
+fn program_result<ID: std::fmt::Debug>(
+    res: Option<&Value<ID>>,
+    index: usize,
 ) -> Result<i64, SteelErr> {
-    let mut state = EvalState::default();
-    let result_index = state.setup_eval(StaticPtr(expr), Vec::new());
-    eval(store, &mut state)?;
-    let res = state.mem_stack.get(result_index.id);
-    debug!("eval: {:#?} {:#?}", state, res);
     match res {
         Some(Value::I64(res)) => Ok(*res),
-        Some(Value::Extern(_func)) => {
-            panic!("Returned an extern func!? {:?}\n{}", res, program_txt)
-        }
-        Some(Value::Uninit) => panic!(
-            "No value was placed in the return address!?\n{}",
-            program_txt
-        ),
-        None => panic!("The return address is out of bounds!?\n{}", program_txt),
+        Some(Value::Bool(res)) => Ok(*res as i64),
+        Some(Value::Unit) => Ok(0),
+        Some(Value::Uninit) => Err(SteelErr::ReliedOnUninitializedMemory(index)),
+        Some(other) => Err(SteelErr::UnexpectedResult(format!("{:?}", other))),
+        None => Err(SteelErr::ReliedOnOutOfBoundsMemory(index)),
     }
 }
 
@@ -217,21 +303,37 @@ mod test {
         }
     }
 
-    fn test_with_random_program<Ctx: CompilerContext>(size: usize) -> String {
+    fn test_with_random_program<Ctx: CompilerContext>(size: usize) -> String
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
         // TODO: use https://docs.rs/crate/quickcheck/0.9.2
-        let spec = Spec::default().sized(size);
-        let mut rng = rand::thread_rng();
-        let mut store = ast::Ast::new();
-        let program = generate_random_program("ast generator", &mut store, &spec, &mut rng);
-        let program = store.pretty(program);
-        take_result(
-            &program,
-            handle::<ecs::Ecs>(Tasks::parse(&program).and_eval()),
-        );
+        let seed = gen_code::seed_from_env();
+        let spec = Spec::default().sized(size).seeded(seed);
+        let mut store = Ctx::new();
+        let root = generate_random_program("generator", &mut store, &spec, &mut spec.rng());
+        let program = store.pretty(root);
+        let eval = |program: &str| handle::<Ctx>(Tasks::parse(program).and_eval());
+        if let Err(err) = eval(&program) {
+            let shrunk = shrink::shrink(&mut store, root, |program| eval(program).is_err());
+            eprintln!("Should be able to eval program (shrunk from the generated one):");
+            eprintln!("{}", shrunk.unwrap_or(program));
+            panic!("error: {:?}\nreplay with STEEL_SEED={}", err, seed);
+        }
+        if let Err(mismatch) = differential::check(&program) {
+            let path = mismatch.write_reproducer(&differential::reproducer_dir());
+            panic!(
+                "{}\nreproducer: {:?}\nreplay with STEEL_SEED={}",
+                mismatch, path, seed
+            );
+        }
         program
     }
 
-    fn test_random_programs<Ctx: CompilerContext>(name: &str, max_size: usize, runs: usize) {
+    fn test_random_programs<Ctx: CompilerContext>(name: &str, max_size: usize, runs: usize)
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
         for i in 1..max_size {
             eprintln!("{}: testing programs of size {:?}", name, i);
             for _run in 0..runs {
@@ -244,6 +346,866 @@ mod test {
     const SIMPLE_PROGRAM: &str = "putchar(48+9)";
     const MEDIUM_PROGRAM: &str = "putchar(65)+putchar(66)+putchar(67)+putchar(10)";
 
+    fn captures_output<Ctx: CompilerContext>() -> Result<(), SteelErr>
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for (program, expected) in [
+            (SIMPLE_PROGRAM, (1, "9")),
+            (MEDIUM_PROGRAM, (4, "ABC\n")),
+            (
+                "let a = print(\"x=\"); let b = putchar(10); print(2*3)",
+                (0, "x=\n6"),
+            ),
+            ("if true then putchar(89) else putchar(78)", (1, "Y")),
+        ] {
+            let mut store = Ctx::new();
+            let (_, expr) = parser::program(&mut store, program)?;
+            let (res, output) = eval_program(&mut store, expr, program)?;
+            assert_eq!((res, output.as_str()), expected, "{}", program);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn captures_output_ast() -> Result<(), SteelErr> {
+        captures_output::<ast::Ast>()
+    }
+
+    #[test]
+    fn captures_output_ecs() -> Result<(), SteelErr> {
+        captures_output::<ecs::Ecs>()
+    }
+
+    #[test]
+    fn reads_input() -> Result<(), SteelErr> {
+        let program = "let echo = (n) => if n == 0 then 0 else (let c = getchar(); if c < 0 then 0 else putchar(c-32)+self(n-1)); echo(3)+getchar()";
+        for (input, expected) in [("abcd", (3 + 100, "ABC")), ("ab", (2 - 1, "AB"))] {
+            let environment = Environment::default().with_io(io::BufferIo::with_input(input));
+            let tasks = Tasks::parse(program)
+                .and_eval()
+                .and_environment(environment);
+            let (_, res, output) = handle::<ast::Ast>(tasks)?;
+            assert_eq!((res, output.as_str()), expected);
+        }
+        let environment = Environment::default().with_io(io::NullIo);
+        let tasks = Tasks::parse(MEDIUM_PROGRAM)
+            .and_eval()
+            .and_environment(environment);
+        assert_eq!(
+            handle::<ast::Ast>(tasks)?,
+            (
+                handle::<ast::Ast>(Tasks::parse(MEDIUM_PROGRAM))?.0,
+                4,
+                String::new()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn eval_errors_point_at_source_ast() {
+        let program = "1+(2*x)";
+        let err = assert_is_err!(handle::<ast::Ast>(Tasks::parse(program).and_eval()));
+        assert_eq!(err.span().map(|span| span.text(program)), Some("x"));
+        assert_eq!(
+            format!("{}", err),
+            "Expected a binding for x in scope at 5..6"
+        );
+    }
+
+    #[test]
+    fn eval_errors_point_at_source_ecs() {
+        let program = "1+(2*x)";
+        let err = assert_is_err!(handle::<ecs::Ecs>(Tasks::parse(program).and_eval()));
+        assert_eq!(err.span().map(|span| span.text(program)), Some("x"));
+        assert_eq!(
+            format!("{}", err),
+            "Expected a binding for x in scope at 5..6"
+        );
+    }
+
+    fn reports_unexpected_results<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for (program, found) in [
+            ("putchar", "extern#putchar"),
+            ("-", "extern#-"),
+            ("1.5", "1.5_f64"),
+            ("\"hi\"", "\"hi\""),
+        ] {
+            assert_err_is!(
+                handle::<Ctx>(Tasks::parse(program).and_eval()),
+                format!(
+                    "Expected the program to evaluate to an i64, bool or unit, found {}",
+                    found
+                )
+            );
+        }
+        let environment = Environment::default()
+            .register("uninit", &[], || Value::Uninit)
+            .unwrap();
+        let tasks = Tasks::parse("uninit()")
+            .and_eval()
+            .and_environment(environment);
+        let err = assert_is_err!(handle::<Ctx>(tasks));
+        assert_eq!(err.code(), "E0007");
+    }
+
+    #[test]
+    fn reports_unexpected_results_ast() {
+        reports_unexpected_results::<ast::Ast>()
+    }
+
+    #[test]
+    fn reports_unexpected_results_ecs() {
+        reports_unexpected_results::<ecs::Ecs>()
+    }
+
+    #[test]
+    fn reports_missing_results() {
+        assert_eq!(
+            program_result::<usize>(None, 3).unwrap_err().code(),
+            "E0008"
+        );
+        assert_eq!(program_result::<usize>(Some(&Value::Unit), 3).ok(), Some(0));
+    }
+
+    fn reports_unknown_nodes<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        let mut store = Ctx::new();
+        let root = store.add(1i64);
+        let _ =
+            <Ctx as compiler_context::NodeStore<Ctx::ID, i64, Ctx::E>>::remove(&mut store, root);
+        let err = assert_is_err!(eval_program(&mut store, root, "1"));
+        assert_eq!(err.code(), "E0024");
+    }
+
+    #[test]
+    fn reports_unknown_nodes_ast() {
+        reports_unknown_nodes::<ast::Ast>()
+    }
+
+    #[test]
+    fn reports_unknown_nodes_ecs() {
+        reports_unknown_nodes::<ecs::Ecs>()
+    }
+
+    #[test]
+    fn reports_incomplete_input() {
+        let err = SteelErr::from(nom::Err::Incomplete(nom::Needed::new(3)));
+        assert_eq!(format!("{}", err), "Expected 3 more bytes of input");
+        let err = SteelErr::from(nom::Err::Incomplete(nom::Needed::Unknown));
+        assert_eq!(format!("{}", err), "Expected more input");
+    }
+
+    fn eval_program_str<Ctx: CompilerContext>(program: &str, tasks: Tasks<Ctx::ID>) -> i64
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        let res = handle::<Ctx>(tasks);
+        match res {
+            Ok((_id, value, _output)) => value,
+            Err(e) => panic!("{}\n{}", program, e),
+        }
+    }
+
+    fn can_eval_let_bindings<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for (program, expected) in [
+            ("let x = 3; let y = x*2; y+x", 9),
+            ("let x = 1; let x = x+1; x*10", 20),
+            ("2*(let x = 4; x-1)", 6),
+            ("let f = +; f(3, 4)", 7),
+        ] {
+            let tasks = Tasks::parse(program).and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+            let tasks = Tasks::parse(program).and_optimize().and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+        }
+    }
+
+    #[test]
+    fn can_eval_let_bindings_ast() {
+        can_eval_let_bindings::<ast::Ast>();
+    }
+
+    #[test]
+    fn can_eval_let_bindings_ecs() {
+        can_eval_let_bindings::<ecs::Ecs>();
+    }
+
+    fn can_eval_lambdas<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for (program, expected) in [
+            ("((x, y) => x*y)(6, 7)", 42),
+            ("((x, y) => x-y)(y=1, x=7)", 6),
+            ("let sq = (x) => x*x; sq(5)", 25),
+            ("let apply = (f, x) => f(x); apply((y) => y+1, 41)", 42),
+            ("let k = 10; let add = (x) => x+k; let k = 1; add(5)", 15),
+            (
+                "let twice = (f) => (x) => f(f(x)); twice((x) => x*3)(2)",
+                18,
+            ),
+        ] {
+            let tasks = Tasks::parse(program).and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+            let tasks = Tasks::parse(program).and_optimize().and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+        }
+    }
+
+    #[test]
+    fn can_eval_lambdas_ast() {
+        can_eval_lambdas::<ast::Ast>();
+    }
+
+    #[test]
+    fn can_eval_lambdas_ecs() {
+        can_eval_lambdas::<ecs::Ecs>();
+    }
+
+    fn bindings_are_scoped<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for (program, expected) in [
+            ("let id = (v) => v; let x = id(v=1, y=2); x", 1),
+            ("let f = (x) => x; f(1)+f(2)*f(3)", 7),
+            ("((n) => if n <= 1 then 1 else n*self(n-1))(5)", 120),
+            (
+                "let fib = (n) => if n < 2 then n else self(n-1)+self(n-2); fib(10)",
+                55,
+            ),
+            ("let x = 1; (let x = 2; x)+x", 3),
+        ] {
+            let tasks = Tasks::parse(program).and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+            let tasks = Tasks::parse(program).and_optimize().and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+        }
+        // An inner call's arguments are gone once it has returned.
+        for (program, name) in [
+            ("let id = (v) => v; let x = id(v=1, y=2); y", "y"),
+            ("let id = (v) => v; id(3)+v", "v"),
+            ("(let z = 1; z)+z", "z"),
+            // Closures only see the bindings from where they were defined (not the caller's).
+            ("let g = () => y; let h = (y) => g(); h(5)", "y"),
+            ("let f = (x) => arg_0; f(7)", "arg_0"),
+        ] {
+            let err = assert_is_err!(handle::<Ctx>(Tasks::parse(program).and_eval()));
+            match err {
+                SteelErr::Located(err, _) => {
+                    assert_eq!(
+                        format!("{}", err),
+                        format!("Expected a binding for {} in scope", name)
+                    )
+                }
+                err => panic!("Expected a located error, found {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn bindings_are_scoped_ast() {
+        bindings_are_scoped::<ast::Ast>();
+    }
+
+    #[test]
+    fn bindings_are_scoped_ecs() {
+        bindings_are_scoped::<ecs::Ecs>();
+    }
+
+    fn can_eval_conditionals<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for (program, expected) in [
+            ("1 < 2", 1),
+            ("3 <= 2", 0),
+            ("2+2 == 4", 1),
+            ("4 != 4", 0),
+            ("1 < 2 && 2 < 3", 1),
+            ("false || false", 0),
+            ("!false", 1),
+            ("if 1 < 2 then 10 else 20", 10),
+            ("if false then 10 else 20", 20),
+            ("let x = 3; if x >= 3 then x*2 else x", 6),
+            ("if true then 2 else missing", 2),
+        ] {
+            let tasks = Tasks::parse(program).and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+            let tasks = Tasks::parse(program).and_optimize().and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+        }
+    }
+
+    #[test]
+    fn can_eval_conditionals_ast() {
+        can_eval_conditionals::<ast::Ast>();
+    }
+
+    #[test]
+    fn can_eval_conditionals_ecs() {
+        can_eval_conditionals::<ecs::Ecs>();
+    }
+
+    #[test]
+    fn conditions_must_be_bools() {
+        let program = "if putchar then 1 else 2";
+        assert_err_is!(
+            handle::<ast::Ast>(Tasks::parse(program).and_eval()),
+            "Expected a bool as the condition, found extern#putchar at 0..24"
+        );
+        let program = "if 1 then 2 else 3";
+        assert_err_is!(
+            handle::<ast::Ast>(Tasks::parse(program).and_optimize().and_eval()),
+            "Expected a bool as the condition, found 1_i64 at 0..18"
+        );
+    }
+
+    fn can_eval_values<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for (program, expected) in [
+            ("len(\"hello\")", 5),
+            ("len(concat(\"ab\", \"c\\n\"))", 4),
+            ("len(\"\")", 0),
+            ("\"abc\" == concat(\"a\", \"bc\")", 1),
+            ("\"abc\" != \"abc\"", 0),
+            ("true == (1 < 2)", 1),
+            ("let s = \"hi\"; if len(s) == 2 then 1 else 0", 1),
+            ("print(\"\")", 0),
+            ("print(\"\") == print(\"\")", 1),
+        ] {
+            let tasks = Tasks::parse(program).and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+            let tasks = Tasks::parse(program).and_optimize().and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+        }
+    }
+
+    #[test]
+    fn can_eval_values_ast() {
+        can_eval_values::<ast::Ast>();
+    }
+
+    #[test]
+    fn can_eval_values_ecs() {
+        can_eval_values::<ecs::Ecs>();
+    }
+
+    fn can_eval_floats<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for (program, expected) in [
+            ("1.5+2.25 == 3.75", 1),
+            ("to_i64(2.5*4.0)", 10),
+            ("to_i64(-7.9)", -7),
+            ("to_i64(7.0/2.0)", 3),
+            ("to_f64(3)/2.0 == 1.5", 1),
+            ("0.1 < 0.2", 1),
+            ("1.0/0.0 > 1e300", 1),
+            ("let nan = 0.0/0.0; nan == nan", 0),
+            ("let x = -1.5; to_i64(x*x*4.0)", 9),
+        ] {
+            let tasks = Tasks::parse(program).and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+            let tasks = Tasks::parse(program).and_optimize().and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+        }
+    }
+
+    #[test]
+    fn can_eval_floats_ast() {
+        can_eval_floats::<ast::Ast>();
+    }
+
+    #[test]
+    fn can_eval_floats_ecs() {
+        can_eval_floats::<ecs::Ecs>();
+    }
+
+    fn can_eval_unary_operators<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for (program, expected) in [
+            ("-3", -3),
+            ("let x = 3; -x", -3),
+            ("let x = 3; -x*2", -6),
+            ("let x = 3; --x", 3),
+            ("let x = 3; 1--x", 4),
+            ("-(2+3)", -5),
+            ("~0", -1),
+            ("let x = 5; ~x", -6),
+            ("let x = 1.5; to_i64(-x*2.0)", -3),
+            ("!(1 < 2) || -1 < 0", 1),
+        ] {
+            let tasks = Tasks::parse(program).and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+            let tasks = Tasks::parse(program).and_optimize().and_eval();
+            assert_eq!(eval_program_str::<Ctx>(program, tasks), expected);
+        }
+    }
+
+    #[test]
+    fn can_eval_unary_operators_ast() {
+        can_eval_unary_operators::<ast::Ast>();
+    }
+
+    #[test]
+    fn can_eval_unary_operators_ecs() {
+        can_eval_unary_operators::<ecs::Ecs>();
+    }
+
+    #[test]
+    fn optimizer_folds_unary_operators() -> Result<(), SteelErr> {
+        let mut store = ast::Ast::new();
+        let program = "f(-(2*3), ~1, -(1.5), -x)";
+        let (id, _, _) = handle_steps(&mut store, Tasks::parse(program).and_optimize())?;
+        assert_eq!(store.pretty(id.unwrap()), "f(-6, -2, -1.5, -x)");
+        Ok(())
+    }
+
+    // The result (or kind of error) of evaluating the program with and without optimizations.
+    fn eval_both_ways<Ctx: CompilerContext>(
+        program: &str,
+        arithmetic: arithmetic::Arithmetic,
+    ) -> [Result<i64, String>; 2]
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        let eval = |tasks: Tasks<Ctx::ID>| {
+            let res = handle::<Ctx>(tasks.and_eval().and_arithmetic(arithmetic));
+            res.map(|(_, res, _)| res).map_err(|err| match err {
+                SteelErr::ArithmeticError(_) => format!("{}", err),
+                err => err.code().to_string(),
+            })
+        };
+        [
+            eval(Tasks::parse(program)),
+            eval(Tasks::parse(program).and_optimize()),
+        ]
+    }
+
+    fn arithmetic_modes<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        use arithmetic::Arithmetic::*;
+        let min = "(0-9223372036854775807-1)";
+        let overflow = |op: &str| Err(format!("Overflow in {}", op));
+        let div_by_zero = || Err("Division by zero".to_string());
+        for (program, wrapping, checked, saturating) in [
+            ("3*4-5", Ok(7), Ok(7), Ok(7)),
+            (
+                "9223372036854775807+1",
+                Ok(i64::MIN),
+                overflow("+"),
+                Ok(i64::MAX),
+            ),
+            ("9223372036854775807*-2", Ok(2), overflow("*"), Ok(i64::MIN)),
+            (
+                &format!("{}/-1", min),
+                Ok(i64::MIN),
+                overflow("/"),
+                Ok(i64::MAX),
+            ),
+            (
+                &format!("-{}", min),
+                Ok(i64::MIN),
+                overflow("-"),
+                Ok(i64::MAX),
+            ),
+            ("1/0", div_by_zero(), div_by_zero(), div_by_zero()),
+            (
+                "let x = 2-2; 5/x",
+                div_by_zero(),
+                div_by_zero(),
+                div_by_zero(),
+            ),
+        ] {
+            for (mode, expected) in [
+                (Wrapping, wrapping),
+                (Checked, checked),
+                (Saturating, saturating),
+            ] {
+                let [unoptimized, optimized] = eval_both_ways::<Ctx>(program, mode);
+                assert_eq!(unoptimized, expected, "{} in {:?}", program, mode);
+                assert_eq!(optimized, expected, "{} in {:?} (optimized)", program, mode);
+            }
+        }
+    }
+
+    #[test]
+    fn arithmetic_modes_ast() {
+        arithmetic_modes::<ast::Ast>();
+    }
+
+    #[test]
+    fn arithmetic_modes_ecs() {
+        arithmetic_modes::<ecs::Ecs>();
+    }
+
+    // Optimizing shouldn't change what a program does, including which errors it reports.
+    fn optimizing_preserves_results<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for size in 1..30 {
+            for _run in 0..20 {
+                let mut store = ast::Ast::new();
+                let spec = Spec::default()
+                    .sized(size)
+                    .seeded(gen_code::seed_from_env());
+                let program =
+                    generate_random_program("ast generator", &mut store, &spec, &mut spec.rng());
+                let program = store.pretty(program);
+                for mode in [
+                    arithmetic::Arithmetic::Wrapping,
+                    arithmetic::Arithmetic::Checked,
+                    arithmetic::Arithmetic::Saturating,
+                ] {
+                    let [unoptimized, optimized] = eval_both_ways::<Ctx>(&program, mode);
+                    assert_eq!(
+                        unoptimized,
+                        optimized,
+                        "{} in {:?} (replay with STEEL_SEED={})",
+                        program,
+                        mode,
+                        spec.seed.unwrap()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn optimizing_preserves_results_ast() {
+        optimizing_preserves_results::<ast::Ast>();
+    }
+
+    #[test]
+    fn optimizing_preserves_results_ecs() {
+        optimizing_preserves_results::<ecs::Ecs>();
+    }
+
+    // The `plus tree` from benches/known_programs.rs.
+    fn plus_tree(depth: usize) -> String {
+        let mut plus_tree = "1".to_string();
+        for _ in 0..depth {
+            plus_tree = format!("({})+({})", plus_tree, plus_tree);
+        }
+        plus_tree
+    }
+
+    fn peak_mem_stack<Ctx: CompilerContext>(source: &str) -> Result<(i64, usize), SteelErr>
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        let mut store = Ctx::new();
+        let (_, expr) = parser::program(&mut store, source)?;
+        let mut evaluator = Evaluator::new(&store, expr);
+        let result_index = evaluator.result_index();
+        evaluator.run()?;
+        let res = match evaluator.result() {
+            Some(Value::I64(res)) => *res,
+            other => panic!("Unexpected result {:?}", other),
+        };
+        let state = evaluator.state();
+        assert_eq!(state.mem_stack.len(), result_index.id + 1, "Leaked memory");
+        Ok((res, state.peak_mem_stack - result_index.id))
+    }
+
+    fn reclaims_memory<Ctx: CompilerContext>() -> Result<(), SteelErr>
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for depth in [1, 4, 12] {
+            let (res, peak) = peak_mem_stack::<Ctx>(&plus_tree(depth))?;
+            assert_eq!(res, 1 << depth);
+            // Each level of the tree holds its arguments and callee until it is done.
+            assert!(peak <= 1 + 4 * depth, "depth {} used {}", depth, peak);
+        }
+        // Closures keep what they capture.
+        let (res, _peak) = peak_mem_stack::<Ctx>("let add = (x) => (y) => x+y; add(1)(2)")?;
+        assert_eq!(res, 3);
+        // But only what they capture, however many calls returned them.
+        for body in ["(x) => x+1", "(x) => x+n", "(let k = n; (x) => x+k)"] {
+            let retained = |depth: usize| -> Result<usize, SteelErr> {
+                let source = format!(
+                    "let loop = (n) => if n == 0 then {} else self(n-1); loop({})",
+                    body, depth
+                );
+                let mut store = Ctx::new();
+                let (_, expr) = parser::program(&mut store, &source)?;
+                let mut evaluator = Evaluator::new(&store, expr);
+                evaluator.run()?;
+                assert!(matches!(evaluator.result(), Some(Value::Func(_))));
+                Ok(evaluator.state().mem_stack.len() - evaluator.result_index().id)
+            };
+            assert_eq!(retained(10)?, retained(100)?, "{}", body);
+            assert!(retained(100)? <= 3, "{}", body);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reclaims_memory_ast() -> Result<(), SteelErr> {
+        reclaims_memory::<ast::Ast>()
+    }
+
+    #[test]
+    fn reclaims_memory_ecs() -> Result<(), SteelErr> {
+        reclaims_memory::<ecs::Ecs>()
+    }
+
+    #[test]
+    fn optimizer_folds_floats() -> Result<(), SteelErr> {
+        let mut store = ecs::Ecs::new();
+        let program = "f(1.5*2.0, 0.5 < 0.25, 1.0/0.0, 1+1.0)";
+        let (id, _, _) = handle_steps(&mut store, Tasks::parse(program).and_optimize())?;
+        assert_eq!(store.pretty(id.unwrap()), "f(3.0, false, 1.0/0.0, 1+1.0)");
+        Ok(())
+    }
+
+    #[test]
+    fn externs_reject_values_of_the_wrong_kind() {
+        for (program, expected) in [
+            (
+                "1 + \"a\"",
+                "Expected i64 for argument arg_1 of +, found \"a\"",
+            ),
+            (
+                "1 + 2.0",
+                "Expected i64 for argument arg_1 of +, found 2.0_f64",
+            ),
+            (
+                "1.5 < 2",
+                "Expected f64 for argument arg_1 of <, found 2_i64",
+            ),
+            (
+                "len(3)",
+                "Expected str for argument arg_0 of len, found 3_i64",
+            ),
+            ("!1", "Expected bool for argument arg_0 of !, found 1_i64"),
+            (
+                "1 == true",
+                "Expected i64 for argument arg_1 of ==, found true",
+            ),
+            (
+                "putchar(\"a\")",
+                "Expected i64 for argument arg_0 of putchar, found \"a\"",
+            ),
+            (
+                "-true",
+                "Expected a number for argument arg_0 of neg, found true",
+            ),
+            (
+                "~1.5",
+                "Expected i64 for argument arg_0 of ~, found 1.5_f64",
+            ),
+        ] {
+            assert_err_is!(
+                handle::<ast::Ast>(Tasks::parse(program).and_eval()),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn optimizer_folds_values() -> Result<(), SteelErr> {
+        let mut store = ast::Ast::new();
+        let program = "f(1 < 2, !true, \"a\" == \"b\", let s = \"x\"; g(s), 1 == true)";
+        let (id, _, _) = handle_steps(&mut store, Tasks::parse(program).and_optimize())?;
+        assert_eq!(
+            store.pretty(id.unwrap()),
+            "f(true, false, false, let s = \"x\"; g(\"x\"), 1==true)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn optimizer_collapses_known_conditionals() -> Result<(), SteelErr> {
+        let mut store = ast::Ast::new();
+        let program = "f(if 2 > 1 then x else y, if !true then x else y)";
+        let (id, _, _) = handle_steps(&mut store, Tasks::parse(program).and_optimize())?;
+        assert_eq!(store.pretty(id.unwrap()), "f(x, y)");
+        Ok(())
+    }
+
+    #[test]
+    fn lambdas_require_their_arguments() {
+        let program = "((x, y) => x+y)(1)";
+        assert_err_is!(
+            handle::<ast::Ast>(Tasks::parse(program).and_eval()),
+            "Expected an argument for parameter y"
+        );
+    }
+
+    #[test]
+    fn optimizer_propagates_let_bound_constants() -> Result<(), SteelErr> {
+        let mut store = ecs::Ecs::new();
+        let program = "let x = 3; let y = x*2; y+f(x=1, x)";
+        let (id, _, _) = handle_steps(&mut store, Tasks::parse(program).and_optimize())?;
+        assert_eq!(
+            store.pretty(id.unwrap()),
+            "let x = 3; let y = 6; 6+f(x=1, 3)"
+        );
+        Ok(())
+    }
+
+    fn evaluator_can_pause_and_resume<Ctx: CompilerContext>() -> Result<(), SteelErr>
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        let mut store = Ctx::new();
+        let (_, expr) = parser::program(&mut store, "let x = 3; if x > 2 then x*2 else x")?;
+        let mut evaluator = Evaluator::new(&store, expr);
+        assert_eq!(
+            evaluator.snapshot().current.as_deref(),
+            Some("let x = 3; if x>2 then x*2 else x")
+        );
+        // Pause when the body of the let is reached.
+        let reached = evaluator.run_until(|evaluator| {
+            let snapshot = evaluator.snapshot();
+            snapshot.current.as_deref() == Some("x*2")
+        })?;
+        assert!(reached);
+        let snapshot = evaluator.snapshot();
+        assert_eq!(
+            snapshot.bindings.keys().collect::<Vec<_>>(),
+            vec!["x"],
+            "{:?}",
+            snapshot
+        );
+        assert!(matches!(snapshot.bindings["x"], Value::I64(3)));
+        assert_eq!(
+            snapshot.current_frame().unwrap().node,
+            evaluator.current_node()
+        );
+        assert!(evaluator.result().is_none());
+        // Steps are counted across pauses.
+        let steps = snapshot.steps;
+        assert_eq!(evaluator.run_for(1)?, 1);
+        assert_eq!(evaluator.snapshot().steps, steps + 1);
+        let remaining = evaluator.run_for(1000)?;
+        assert!(remaining < 1000);
+        assert!(evaluator.is_done());
+        assert!(matches!(evaluator.result(), Some(Value::I64(6))));
+        assert!(!evaluator.step()?);
+        assert!(!evaluator.run_until(|_| true)?);
+        assert!(evaluator.snapshot().frames.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn evaluator_can_pause_and_resume_ast() -> Result<(), SteelErr> {
+        evaluator_can_pause_and_resume::<ast::Ast>()
+    }
+
+    #[test]
+    fn evaluator_can_pause_and_resume_ecs() -> Result<(), SteelErr> {
+        evaluator_can_pause_and_resume::<ecs::Ecs>()
+    }
+
+    #[test]
+    fn evaluator_resumes_after_raising_limits() -> Result<(), SteelErr> {
+        let mut store = ast::Ast::new();
+        let (_, expr) = parser::program(&mut store, "((n) => if n < 10 then self(n+1) else n)(0)")?;
+        let mut evaluator =
+            Evaluator::new(&store, expr).with_limits(Limits::default().max_steps(20));
+        match assert_is_err!(evaluator.run()) {
+            SteelErr::ResourceLimitExceeded(limit) => assert_eq!(limit, Limit::Steps(20)),
+            err => panic!("Expected the step limit to be exceeded, found {}", err),
+        }
+        assert_eq!(evaluator.snapshot().steps, 20);
+        evaluator.set_limits(Limits::default());
+        evaluator.run()?;
+        assert!(matches!(evaluator.result(), Some(Value::I64(10))));
+        Ok(())
+    }
+
+    const NON_TERMINATING_PROGRAM: &str = "((n) => self(n+1))(0)";
+
+    fn limits_stop_evaluation<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for (limits, expected) in [
+            (Limits::default().max_steps(1000), Limit::Steps(1000)),
+            (Limits::default().max_mem(500), Limit::Memory(500)),
+            (Limits::default().max_depth(200), Limit::Depth(200)),
+        ] {
+            let tasks = Tasks::parse(NON_TERMINATING_PROGRAM)
+                .and_eval()
+                .and_limits(limits);
+            match assert_is_err!(handle::<Ctx>(tasks)) {
+                SteelErr::ResourceLimitExceeded(limit) => assert_eq!(limit, expected),
+                err => panic!("Expected {:?} to be exceeded, found {}", expected, err),
+            }
+        }
+        // Programs within the limits are unaffected.
+        let tasks = Tasks::parse("((n) => if n < 10 then self(n+1) else n)(0)")
+            .and_eval()
+            .and_limits(Limits::default().max_steps(1000).max_depth(200));
+        assert_eq!(handle::<Ctx>(tasks).unwrap().1, 10);
+    }
+
+    #[test]
+    fn limits_stop_evaluation_ast() {
+        limits_stop_evaluation::<ast::Ast>();
+    }
+
+    #[test]
+    fn limits_stop_evaluation_ecs() {
+        limits_stop_evaluation::<ecs::Ecs>();
+    }
+
+    #[test]
+    #[timeout(10000)]
+    fn evaluation_can_be_cancelled() {
+        use std::sync::{atomic::AtomicBool, atomic::Ordering, Arc};
+        let cancel = Arc::new(AtomicBool::new(false));
+        let limits = Limits::default().cancel_on(cancel.clone());
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            cancel.store(true, Ordering::Relaxed);
+        });
+        let tasks = Tasks::parse(NON_TERMINATING_PROGRAM)
+            .and_eval()
+            .and_limits(limits);
+        let err = assert_is_err!(handle::<ast::Ast>(tasks));
+        assert_eq!(format!("{}", err), "Evaluation was cancelled");
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn cannot_eval_invalid_nodes() {
+        let program = "putchar(#)";
+        let mut store = ast::Ast::new();
+        let (root, errors) = program_recovering(&mut store, program);
+        assert_eq!(errors.len(), 1);
+        let err = assert_is_err!(handle_steps(
+            &mut store,
+            Tasks::pre_parsed(root.unwrap()).and_eval()
+        ));
+        assert_eq!(
+            format!("{}", err),
+            "Cannot evaluate code that failed to parse: Expected an argument, found \"#\" at 8..9"
+        );
+    }
+
     #[test]
     fn cannot_handle_devious_program_ast() {
         let program = DEVIOUS_PROGRAM;
@@ -272,6 +1234,112 @@ mod test {
         );
     }
 
+    #[test]
+    fn seeded_programs_are_reproducible() {
+        let generate = |seed| {
+            let spec = Spec::default().sized(50).seeded(seed);
+            let mut ast = ast::Ast::new();
+            let mut ecs = ecs::Ecs::new();
+            let from_ast = generate_random_program("ast", &mut ast, &spec, &mut spec.rng());
+            let from_ecs = generate_random_program("ecs", &mut ecs, &spec, &mut spec.rng());
+            let program = ast.pretty(from_ast);
+            assert_eq!(program, ecs.pretty(from_ecs));
+            program
+        };
+        assert_eq!(generate(1), generate(1));
+        assert_ne!(generate(1), generate(2));
+    }
+
+    // The number of nodes on the longest path from `id` to a leaf (and the most args in a call).
+    fn depth_and_arity<Ctx: CompilerContext>(store: &Ctx, id: Ctx::ID) -> (usize, usize) {
+        let (children, arity) = if let Ok(call) = store.get_call(id) {
+            let mut children = vec![call.callee];
+            children.extend(call.args.iter().map(|(_name, arg)| *arg));
+            (children, call.args.len())
+        } else if let Ok(binding) = store.get_let(id) {
+            (vec![binding.value, binding.body], 0)
+        } else if let Ok(conditional) = store.get_if(id) {
+            let children = vec![
+                conditional.condition,
+                conditional.then,
+                conditional.otherwise,
+            ];
+            (children, 0)
+        } else if let Ok(lambda) = store.get_lambda(id) {
+            (vec![lambda.body], 0)
+        } else {
+            (vec![], 0)
+        };
+        children
+            .into_iter()
+            .map(|child| depth_and_arity(store, child))
+            .fold((1, arity), |(depth, arity), (child_depth, child_arity)| {
+                (depth.max(child_depth + 1), arity.max(child_arity))
+            })
+    }
+
+    #[test]
+    fn specs_limit_depth_and_arity() {
+        for weights in [gen_code::Weights::default(), gen_code::Weights::all_nodes()] {
+            for seed in 0..100 {
+                let spec = Spec::default()
+                    .sized(200)
+                    .seeded(seed)
+                    .weighted(weights.clone())
+                    .max_depth(4)
+                    .max_arity(2);
+                let mut store = ast::Ast::new();
+                let root = generate_random_program("limited", &mut store, &spec, &mut spec.rng());
+                let (depth, arity) = depth_and_arity(&store, root);
+                let program = store.pretty(root);
+                assert!(depth <= 5, "depth {} of {}", depth, program);
+                assert!(arity <= 2, "arity {} of {}", arity, program);
+            }
+        }
+    }
+
+    fn well_typed_programs_evaluate<Ctx: CompilerContext>()
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        for weights in [gen_code::Weights::default(), gen_code::Weights::all_nodes()] {
+            for size in 1..40 {
+                let seed = gen_code::seed_from_env();
+                let spec = Spec::default()
+                    .sized(size)
+                    .seeded(seed)
+                    .weighted(weights.clone())
+                    .well_typed();
+                let mut store = Ctx::new();
+                let root = generate_random_program("typed", &mut store, &spec, &mut spec.rng());
+                let program = store.pretty(root);
+                // Only arithmetic (i.e. dividing by zero) can go wrong.
+                match handle::<Ctx>(Tasks::parse(&program).and_eval()) {
+                    Ok(_) | Err(SteelErr::ArithmeticError(_)) => {}
+                    Err(SteelErr::Located(err, _))
+                        if matches!(*err, SteelErr::ArithmeticError(_)) => {}
+                    Err(err) => panic!(
+                        "{} failed with {}\nreplay with STEEL_SEED={}",
+                        program, err, seed
+                    ),
+                }
+                if let Err(mismatch) = differential::check(&program) {
+                    panic!("{}\nreplay with STEEL_SEED={}", mismatch, seed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn well_typed_programs_evaluate_ast() {
+        well_typed_programs_evaluate::<ast::Ast>();
+    }
+
+    #[test]
+    fn well_typed_programs_evaluate_ecs() {
+        well_typed_programs_evaluate::<ecs::Ecs>();
+    }
+
     #[test]
     fn can_handle_small_random_programs_ast() {
         test_random_programs::<ast::Ast>("ast", 50, 100);
@@ -4,6 +4,17 @@ pub enum Operator {
     Sub,
     Mul,
     Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Not,
+    Neg,
+    BitNot,
 }
 
 impl std::fmt::Display for Operator {
@@ -13,6 +24,14 @@ impl std::fmt::Display for Operator {
 }
 
 impl Operator {
+    // Longer operators first, so that they can be matched greedily.
+    pub fn all() -> &'static [Operator] {
+        use Operator::*;
+        &[
+            Eq, Ne, Le, Ge, And, Or, Lt, Gt, Not, Add, Sub, Mul, Div, Neg, BitNot,
+        ]
+    }
+
     pub fn to_str(&self) -> &str {
         use Operator::*;
         match self {
@@ -20,14 +39,94 @@ impl Operator {
             Sub => "-",
             Mul => "*",
             Div => "/",
+            Eq => "==",
+            Ne => "!=",
+            Lt => "<",
+            Le => "<=",
+            Gt => ">",
+            Ge => ">=",
+            And => "&&",
+            Or => "||",
+            Not => "!",
+            Neg => "-",
+            BitNot => "~",
+        }
+    }
+
+    pub fn is_unary(&self) -> bool {
+        use Operator::*;
+        matches!(self, Not | Neg | BitNot)
+    }
+
+    // The name of the extern that implements the operator (`-` is also subtraction).
+    pub fn extern_name(&self) -> &str {
+        match self {
+            Operator::Neg => "neg",
+            _ => self.to_str(),
         }
     }
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct Span {
+    // Byte offsets into the source text.
+    pub start: usize,
+    pub end: usize,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    // Find the span between two suffixes of `source` (i.e. what the parser sees before and after
+    // consuming some input).
+    pub fn between(source: &str, before: &str, after: &str) -> Self {
+        Self::new(source.len() - before.len(), source.len() - after.len())
+    }
+
+    pub fn join(&self, other: &Span) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    pub fn len(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        source.get(self.start..self.end).unwrap_or("")
+    }
+
+    // 1-indexed line and column (in chars) of the start of the span.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = source.get(..self.start).unwrap_or(source);
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let col = before[line_start..].chars().count() + 1;
+        (line, col)
+    }
+}
+
+// Comments around a node (kept so that the pretty printer can re-emit them).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct Trivia {
+    pub leading: Vec<String>,  // e.g. `// ...` or `/* ... */` before the node.
+    pub trailing: Vec<String>, // comments after the end of the program.
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub struct Symbol {
     // TODO: Intern strings
-    // TODO: Locations
     pub name: String,
 }
 
@@ -39,6 +138,85 @@ impl Symbol {
     }
 }
 
+// Stands in for input that could not be parsed (when recovering from parse errors).
+#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct Invalid {
+    pub text: String,
+    pub message: String,
+}
+
+impl Invalid {
+    pub fn new(text: &str, message: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+// A string literal e.g. `"hello\n"` (with escapes already processed).
+#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct StrLiteral {
+    pub value: String,
+}
+
+impl StrLiteral {
+    pub fn new(value: &str) -> Self {
+        Self {
+            value: value.to_string(),
+        }
+    }
+}
+
+// `let name = value; body`
+#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct Let<P> {
+    pub name: String,
+    pub value: P,
+    pub body: P,
+}
+
+impl<P> Let<P> {
+    pub fn new(name: &str, value: P, body: P) -> Self {
+        Self {
+            name: name.to_string(),
+            value,
+            body,
+        }
+    }
+}
+
+// `if condition then a else b`
+#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct If<P> {
+    pub condition: P,
+    pub then: P,
+    pub otherwise: P,
+}
+
+impl<P> If<P> {
+    pub fn new(condition: P, then: P, otherwise: P) -> Self {
+        Self {
+            condition,
+            then,
+            otherwise,
+        }
+    }
+}
+
+// `(params...) => body`
+#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct Lambda<P> {
+    pub params: Vec<String>,
+    pub body: P,
+}
+
+impl<P> Lambda<P> {
+    pub fn new(params: Vec<String>, body: P) -> Self {
+        Self { params, body }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub struct Call<P> {
     pub callee: P,
@@ -70,5 +248,21 @@ impl<P: Clone> Call<P> {
 
 #[cfg(test)]
 mod test {
-    // use super::*;
+    use super::*;
+
+    #[test]
+    fn span_between_suffixes() {
+        let source = "foo(12, a)";
+        let span = Span::between(source, &source[4..], &source[6..]);
+        assert_eq!(span, Span::new(4, 6));
+        assert_eq!(span.text(source), "12");
+    }
+
+    #[test]
+    fn span_line_col() {
+        let source = "a+\n  b*c\n";
+        assert_eq!(Span::new(0, 1).line_col(source), (1, 1));
+        assert_eq!(Span::new(5, 6).line_col(source), (2, 3));
+        assert_eq!(Span::new(10, 10).line_col(source), (3, 1));
+    }
 }
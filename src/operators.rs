@@ -0,0 +1,195 @@
+use crate::nodes::Operator;
+
+// Higher precedences bind more tightly.
+pub type Precedence = i32;
+pub const MIN_PRECEDENCE: Precedence = Precedence::MIN;
+
+pub const OR_PRECEDENCE: Precedence = 1;
+pub const AND_PRECEDENCE: Precedence = 2;
+pub const EQUALITY_PRECEDENCE: Precedence = 3;
+pub const COMPARISON_PRECEDENCE: Precedence = 4;
+pub const PLUS_PRECEDENCE: Precedence = 5;
+pub const MUL_PRECEDENCE: Precedence = 6;
+pub const PREFIX_PRECEDENCE: Precedence = 7;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Assoc {
+    Left,  // e.g. `a-b-c` is `(a-b)-c`
+    Right, // e.g. `a**b**c` is `a**(b**c)`
+    Non,   // e.g. `a==b==c` is an error
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fixity {
+    Prefix,
+    Infix,
+    Postfix,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OperatorInfo {
+    pub symbol: String,
+    pub precedence: Precedence,
+    pub assoc: Assoc,
+    pub fixity: Fixity,
+}
+
+impl OperatorInfo {
+    pub fn new(symbol: &str, precedence: Precedence, assoc: Assoc, fixity: Fixity) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            precedence,
+            assoc,
+            fixity,
+        }
+    }
+
+    // Operators without a builtin are called by name (i.e. as a `Symbol`).
+    pub fn builtin(&self) -> Option<Operator> {
+        let unary = self.fixity == Fixity::Prefix;
+        Operator::all()
+            .iter()
+            .find(|op| op.to_str() == self.symbol && op.is_unary() == unary)
+            .cloned()
+    }
+
+    // The minimum precedence of operators in the right hand operand.
+    pub fn right_precedence(&self) -> Precedence {
+        match self.assoc {
+            Assoc::Right => self.precedence,
+            Assoc::Left | Assoc::Non => self.precedence + 1,
+        }
+    }
+
+    // Whether an operand with the given precedence needs parentheses (on the `left` or right).
+    pub fn needs_parens(&self, operand: Precedence, left: bool) -> bool {
+        let keeps_assoc = match self.assoc {
+            Assoc::Left => left,
+            Assoc::Right => !left,
+            Assoc::Non => false,
+        };
+        operand < self.precedence || (operand == self.precedence && !keeps_assoc)
+    }
+}
+
+// The operators known to the parser and pretty printer.
+// Embedders can register their own (made of punctuation, so that they aren't read as symbols).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OperatorTable {
+    operators: Vec<OperatorInfo>,
+}
+
+impl Default for OperatorTable {
+    fn default() -> Self {
+        use Assoc::*;
+        Self::empty()
+            .infix("||", OR_PRECEDENCE, Left)
+            .infix("&&", AND_PRECEDENCE, Left)
+            .infix("==", EQUALITY_PRECEDENCE, Non)
+            .infix("!=", EQUALITY_PRECEDENCE, Non)
+            .infix("<", COMPARISON_PRECEDENCE, Non)
+            .infix("<=", COMPARISON_PRECEDENCE, Non)
+            .infix(">", COMPARISON_PRECEDENCE, Non)
+            .infix(">=", COMPARISON_PRECEDENCE, Non)
+            .infix("+", PLUS_PRECEDENCE, Left)
+            .infix("-", PLUS_PRECEDENCE, Left)
+            .infix("*", MUL_PRECEDENCE, Left)
+            .infix("/", MUL_PRECEDENCE, Left)
+            .prefix("!", PREFIX_PRECEDENCE)
+            .prefix("-", PREFIX_PRECEDENCE)
+            .prefix("~", PREFIX_PRECEDENCE)
+    }
+}
+
+impl OperatorTable {
+    pub fn empty() -> Self {
+        Self { operators: vec![] }
+    }
+
+    // Add an operator, replacing any with the same symbol and fixity.
+    pub fn register(mut self, info: OperatorInfo) -> Self {
+        self.operators
+            .retain(|op| op.symbol != info.symbol || op.fixity != info.fixity);
+        self.operators.push(info);
+        self
+    }
+
+    pub fn infix(self, symbol: &str, precedence: Precedence, assoc: Assoc) -> Self {
+        self.register(OperatorInfo::new(symbol, precedence, assoc, Fixity::Infix))
+    }
+
+    pub fn prefix(self, symbol: &str, precedence: Precedence) -> Self {
+        self.register(OperatorInfo::new(
+            symbol,
+            precedence,
+            Assoc::Right,
+            Fixity::Prefix,
+        ))
+    }
+
+    pub fn postfix(self, symbol: &str, precedence: Precedence) -> Self {
+        self.register(OperatorInfo::new(
+            symbol,
+            precedence,
+            Assoc::Left,
+            Fixity::Postfix,
+        ))
+    }
+
+    pub fn find(&self, symbol: &str, fixity: Fixity) -> Option<&OperatorInfo> {
+        self.operators
+            .iter()
+            .find(|op| op.symbol == symbol && op.fixity == fixity)
+    }
+
+    pub fn prefixes(&self) -> impl Iterator<Item = &str> {
+        self.operators
+            .iter()
+            .filter(|op| op.fixity == Fixity::Prefix)
+            .map(|op| op.symbol.as_str())
+    }
+
+    pub fn contains(&self, symbol: &str) -> bool {
+        self.operators.iter().any(|op| op.symbol == symbol)
+    }
+
+    // The longest operator with this fixity at the start of the input.
+    pub fn lookup(&self, input: &str, fixity: Fixity) -> Option<&OperatorInfo> {
+        self.operators
+            .iter()
+            .filter(|op| op.fixity == fixity && input.starts_with(&op.symbol))
+            .max_by_key(|op| op.symbol.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup_prefers_longest_operator() {
+        let table = OperatorTable::default();
+        let found = table
+            .lookup("<=x", Fixity::Infix)
+            .map(|op| op.symbol.as_str());
+        assert_eq!(found, Some("<="));
+        assert_eq!(table.lookup("!x", Fixity::Infix), None);
+        assert_eq!(
+            table.lookup("!=x", Fixity::Prefix).map(|op| op.builtin()),
+            Some(Some(Operator::Not))
+        );
+        let minus = |fixity| table.lookup("-x", fixity).and_then(|op| op.builtin());
+        assert_eq!(minus(Fixity::Prefix), Some(Operator::Neg));
+        assert_eq!(minus(Fixity::Infix), Some(Operator::Sub));
+    }
+
+    #[test]
+    fn register_replaces_operators() {
+        let table = OperatorTable::default().infix("+", 10, Assoc::Right);
+        let plus = table.find("+", Fixity::Infix).unwrap();
+        assert_eq!((plus.precedence, plus.assoc), (10, Assoc::Right));
+        assert_eq!(table.find("**", Fixity::Infix), None);
+        let table = table.infix("**", 8, Assoc::Right);
+        assert_eq!(table.find("**", Fixity::Infix).unwrap().builtin(), None);
+    }
+}
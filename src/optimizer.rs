@@ -1,11 +1,16 @@
+use crate::arithmetic::Arithmetic;
 use crate::compiler_context::CompilerContext;
-use crate::nodes::Operator;
+use crate::nodes::{Operator, StrLiteral};
+use std::collections::BTreeSet;
 // use log::{debug, trace};
 
 #[derive(Default, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
 #[non_exhaustive]
 pub struct Optimizations {
     constant_folding: bool,
+    let_propagation: bool,
+    arithmetic: Arithmetic,         // must match the interpreter's.
+    unfoldable: BTreeSet<Operator>, // operators the environment doesn't bind to the builtins.
 }
 
 impl Optimizations {
@@ -20,14 +25,120 @@ impl Optimizations {
         }
     }
 
+    pub fn and_let_propagation(self) -> Self {
+        Self {
+            let_propagation: true,
+            ..self
+        }
+    }
+
+    pub fn with_arithmetic(self, arithmetic: Arithmetic) -> Self {
+        Self { arithmetic, ..self }
+    }
+
+    // Leave calls to these operators for the interpreter (e.g. as the embedder overrides them).
+    pub fn without_folding(self, operators: impl IntoIterator<Item = Operator>) -> Self {
+        Self {
+            unfoldable: operators.into_iter().collect(),
+            ..self
+        }
+    }
+
     pub fn all(self) -> Self {
-        self.and_constant_folding()
+        self.and_constant_folding().and_let_propagation()
+    }
+}
+
+// A literal value that the optimizer can compute with.
+#[derive(Clone, Debug, PartialEq)]
+enum Constant {
+    I64(i64),
+    F64(f64),
+    Bool(bool),
+    Str(String),
+}
+
+fn constant<C: CompilerContext + ?Sized>(context: &C, id: C::ID) -> Option<Constant> {
+    if let Ok(v) = context.get_i64(id) {
+        Some(Constant::I64(*v))
+    } else if let Ok(v) = context.get_f64(id) {
+        Some(Constant::F64(*v))
+    } else if let Ok(v) = context.get_bool(id) {
+        Some(Constant::Bool(*v))
+    } else if let Ok(s) = context.get_str(id) {
+        Some(Constant::Str(s.value.to_string()))
+    } else {
+        None
+    }
+}
+
+fn replace_with_constant<C: CompilerContext + ?Sized>(
+    context: &mut C,
+    id: C::ID,
+    value: Constant,
+) -> Result<(), C::E> {
+    match value {
+        Constant::I64(v) => context.replace(id, v),
+        Constant::F64(v) => context.replace(id, v),
+        Constant::Bool(v) => context.replace(id, v),
+        Constant::Str(s) => context.replace(id, StrLiteral::new(&s)),
+    }
+}
+
+// Results that would be errors at run time (e.g. `1/0`) are left for the interpreter to report.
+fn fold(
+    arithmetic: Arithmetic,
+    op: Operator,
+    left: Constant,
+    right: Option<Constant>,
+) -> Option<Constant> {
+    use Constant::*;
+    use Operator::*;
+    let right = if let Some(right) = right {
+        right
+    } else {
+        return match (op, left) {
+            (Not, Bool(v)) => Some(Bool(!v)),
+            (Neg, I64(v)) => arithmetic.negate(v).ok().map(I64),
+            (Neg, F64(v)) => Some(F64(-v)),
+            (BitNot, I64(v)) => Some(I64(!v)),
+            _ => None,
+        };
+    };
+    let result = match (op, left, right) {
+        (Add | Sub | Mul | Div, I64(l), I64(r)) => I64(arithmetic.binary(op, l, r).ok()?),
+        (Add, F64(l), F64(r)) => F64(l + r),
+        (Sub, F64(l), F64(r)) => F64(l - r),
+        (Mul, F64(l), F64(r)) => F64(l * r),
+        (Div, F64(l), F64(r)) => F64(l / r),
+        (Lt, I64(l), I64(r)) => Bool(l < r),
+        (Le, I64(l), I64(r)) => Bool(l <= r),
+        (Gt, I64(l), I64(r)) => Bool(l > r),
+        (Ge, I64(l), I64(r)) => Bool(l >= r),
+        (Lt, F64(l), F64(r)) => Bool(l < r),
+        (Le, F64(l), F64(r)) => Bool(l <= r),
+        (Gt, F64(l), F64(r)) => Bool(l > r),
+        (Ge, F64(l), F64(r)) => Bool(l >= r),
+        (And, Bool(l), Bool(r)) => Bool(l && r),
+        (Or, Bool(l), Bool(r)) => Bool(l || r),
+        (Eq | Ne, l, r) => {
+            if std::mem::discriminant(&l) != std::mem::discriminant(&r) {
+                return None; // This is a type error, leave it for the interpreter to report.
+            }
+            Bool((l == r) == (op == Eq))
+        }
+        _ => return None,
+    };
+    match result {
+        F64(v) if !v.is_finite() => None, // There is no literal for these (e.g. `1.0/0.0`).
+        result => Some(result),
     }
 }
 
 fn constant_folding<C: CompilerContext + ?Sized + std::fmt::Debug>(
     context: &mut C,
-    replace: &mut Vec<(C::ID, i64)>,
+    optimizations: &Optimizations,
+    replace: &mut Vec<(C::ID, Constant)>,
     root: C::ID,
     fixed_point: &mut bool,
 ) -> Result<C::ID, C::E> {
@@ -35,45 +146,155 @@ fn constant_folding<C: CompilerContext + ?Sized + std::fmt::Debug>(
     // ECS will run the Call component, but AST has to traverse all the nodes to check if they
     // are Calls.
     context.for_each_call(&mut |context, id, call| {
-        let name = if let Ok(name) = context.get_operator(call.callee) {
-            name
-        } else {
-            return; // skip now
+        let name = match context.get_operator(call.callee) {
+            Ok(name) if !optimizations.unfoldable.contains(name) => *name,
+            _ => return, // skip now
         };
-        let left: i64 = if let Some(left) = call.left {
-            if let Ok(left) = context.get_i64(left) {
-                *left
-            } else {
-                return;
-            }
+        let left = if let Some(left) = call.left.and_then(|left| constant(context, left)) {
+            left
         } else {
             return;
         };
-        let right: i64 = if let Some(right) = call.right {
-            if let Ok(right) = context.get_i64(right) {
-                *right
+        let right = if let Some(right) = call.right {
+            if let Some(right) = constant(context, right) {
+                Some(right)
             } else {
                 return;
             }
         } else {
-            return;
+            None
         };
-        use Operator::*;
-        let result = match name {
-            Add => left.wrapping_add(right),
-            Sub => left.wrapping_sub(right),
-            Mul => left.wrapping_mul(right),
-            Div => left.wrapping_div(right),
+        if let Some(result) = fold(optimizations.arithmetic, name, left, right) {
+            // Update so that we don't have to re-find the updated values
+            replace.push((id, result));
+            *fixed_point = false;
+        }
+    })?;
+    for (id, value) in replace.drain(..) {
+        // This is the bit that does the updates in place...
+        replace_with_constant(context, id, value)?;
+    }
+
+    // Collapse conditionals with a known condition into the taken branch.
+    let mut ifs = vec![];
+    context.walk(root, &mut |context, id| {
+        if let Ok(conditional) = context.get_if(id) {
+            if let Ok(condition) = context.get_bool(conditional.condition) {
+                let branch = if *condition {
+                    conditional.then
+                } else {
+                    conditional.otherwise
+                };
+                ifs.push((id, branch));
+            }
+        }
+    });
+    for (id, branch) in ifs {
+        copy_node(context, branch, id)?;
+        *fixed_point = false;
+    }
+    Ok(root)
+}
+
+// Overwrite `target` with a copy of the node at `source` (children are shared, not copied).
+fn copy_node<C: CompilerContext + ?Sized>(
+    context: &mut C,
+    source: C::ID,
+    target: C::ID,
+) -> Result<(), C::E> {
+    if let Some(value) = constant(context, source) {
+        replace_with_constant(context, target, value)
+    } else if let Ok(op) = context.get_operator(source) {
+        let op = *op;
+        context.replace(target, op)
+    } else if let Ok(symbol) = context.get_symbol(source) {
+        let symbol = symbol.clone();
+        context.replace(target, symbol)
+    } else if let Ok(call) = context.get_call(source) {
+        let call = call.clone();
+        context.replace(target, call)
+    } else if let Ok(binding) = context.get_let(source) {
+        let binding = binding.clone();
+        context.replace(target, binding)
+    } else if let Ok(lambda) = context.get_lambda(source) {
+        let lambda = lambda.clone();
+        context.replace(target, lambda)
+    } else if let Ok(conditional) = context.get_if(source) {
+        let conditional = conditional.clone();
+        context.replace(target, conditional)
+    } else {
+        let invalid = context.get_invalid(source)?.clone();
+        context.replace(target, invalid)
+    }
+}
+
+// Find the uses of `name` in `id` that would see the binding (i.e. aren't shadowed).
+fn find_uses<C: CompilerContext + ?Sized>(
+    context: &C,
+    name: &str,
+    id: C::ID,
+    uses: &mut Vec<C::ID>,
+) {
+    if let Ok(symbol) = context.get_symbol(id) {
+        if symbol.name == name {
+            uses.push(id);
+        }
+    } else if let Ok(call) = context.get_call(id) {
+        for (_arg_name, arg) in &call.args {
+            find_uses(context, name, *arg, uses);
+        }
+        // The callee is evaluated with its arguments (and `self`) bound.
+        if name != "self" && call.args.iter().all(|(arg_name, _)| arg_name != name) {
+            find_uses(context, name, call.callee, uses);
+        }
+    } else if let Ok(binding) = context.get_let(id) {
+        find_uses(context, name, binding.value, uses);
+        if binding.name != name {
+            find_uses(context, name, binding.body, uses);
+        }
+    } else if let Ok(lambda) = context.get_lambda(id) {
+        if name != "self" && lambda.params.iter().all(|param| param != name) {
+            find_uses(context, name, lambda.body, uses);
+        }
+    } else if let Ok(conditional) = context.get_if(id) {
+        find_uses(context, name, conditional.condition, uses);
+        find_uses(context, name, conditional.then, uses);
+        find_uses(context, name, conditional.otherwise, uses);
+    }
+}
+
+fn find_lets<C: CompilerContext + ?Sized>(context: &C, id: C::ID, lets: &mut Vec<C::ID>) {
+    context.walk(id, &mut |context, id| {
+        if context.get_let(id).is_ok() {
+            lets.push(id);
+        }
+    });
+}
+
+fn let_propagation<C: CompilerContext + ?Sized + std::fmt::Debug>(
+    context: &mut C,
+    replace: &mut Vec<(C::ID, Constant)>,
+    root: C::ID,
+    fixed_point: &mut bool,
+) -> Result<C::ID, C::E> {
+    // Replace uses of let bound constants with the constant.
+    let mut lets = vec![];
+    find_lets(context, root, &mut lets);
+    for id in lets {
+        let binding = context.get_let(id)?;
+        let value = if let Some(value) = constant(context, binding.value) {
+            value
+        } else {
+            continue;
         };
-        // Update so that we don't have to re-find the updated values
-        replace.push((id, result));
+        let mut uses = vec![];
+        find_uses(context, &binding.name, binding.body, &mut uses);
+        replace.extend(uses.into_iter().map(|use_id| (use_id, value.clone())));
+    }
+    for (id, value) in replace.drain(..) {
+        replace_with_constant(context, id, value)?;
         *fixed_point = false;
-    })?;
-    for (id, value) in replace.iter() {
-        // println!("REPLACING {:?} with value: {}", id, value);
-        context.replace(*id, *value)?; // This is the bit that does the updates in place...
     }
-    replace.clear(); // no need to replace nodes twice (but keep the capacity for later).
     Ok(root)
 }
 
@@ -84,11 +305,14 @@ pub fn optimize<C: CompilerContext + ?Sized + std::fmt::Debug>(
 ) -> Result<C::ID, C::E> {
     // Replace nodes
     let mut fixed_point;
-    let mut replace: Vec<(C::ID, i64)> = Vec::new();
+    let mut replace: Vec<(C::ID, Constant)> = Vec::new();
     loop {
         fixed_point = true;
+        if optimizations.let_propagation {
+            root = let_propagation(context, &mut replace, root, &mut fixed_point)?;
+        }
         if optimizations.constant_folding {
-            root = constant_folding(context, &mut replace, root, &mut fixed_point)?;
+            root = constant_folding(context, optimizations, &mut replace, root, &mut fixed_point)?;
         }
         if fixed_point {
             break;
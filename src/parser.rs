@@ -1,27 +1,124 @@
 use crate::compiler_context::{CompilerContext, NodeStore};
 use crate::error::SteelErr;
-use crate::nodes::{Call, Operator, Symbol};
+use crate::nodes::{Call, If, Invalid, Lambda, Let, Span, StrLiteral, Symbol, Trivia};
+use crate::operators::{Assoc, Fixity, OperatorInfo, OperatorTable, Precedence, MIN_PRECEDENCE};
 use nom::{
     branch::alt,
     bytes::complete::{tag as raw_tag, take_while, take_while1},
-    character::complete::{alpha1, multispace0},
+    character::complete::alpha1,
     combinator::map_res,
     multi::separated_list0,
     sequence::tuple,
 };
+use std::collections::HashMap;
 
 type SResult<'a, T> = std::result::Result<(&'a str, T), nom::Err<SteelErr>>;
 
+#[derive(Debug)]
+pub struct ParseState<'source> {
+    source: &'source str,
+    recovering: bool,
+    errors: Vec<SteelErr>,   // errors that have been recovered from.
+    newline_ends_expr: bool, // e.g. `let x = 1` followed by a new line.
+    operators: OperatorTable,
+}
+
+impl<'source> ParseState<'source> {
+    pub fn new(source: &'source str) -> Self {
+        Self {
+            source,
+            recovering: false,
+            errors: Vec::new(),
+            newline_ends_expr: false,
+            operators: OperatorTable::default(),
+        }
+    }
+
+    pub fn with_operators(self, operators: OperatorTable) -> Self {
+        Self { operators, ..self }
+    }
+
+    pub fn recovering(self) -> Self {
+        Self {
+            recovering: true,
+            ..self
+        }
+    }
+}
+
+// Whitespace and comments (`// ...` up to the end of the line, or `/* ... */`) between tokens.
+pub fn trivia(input: &str) -> SResult<'_, &str> {
+    let mut rest = input;
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', '\r', '\n']);
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map(|end| &rest[end..]).unwrap_or("");
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            if let Some(end) = comment.find("*/") {
+                rest = &comment[end + 2..];
+            } else {
+                return Err(nom::Err::Error(SteelErr::MalformedExpression(
+                    rest.to_string(),
+                    "the end of the block comment".to_string(),
+                )));
+            }
+        } else {
+            return Ok((rest, &input[..input.len() - rest.len()]));
+        }
+    }
+}
+
+// Find the comments in the source (skipping over string literals).
+// Steps over whole chars, so that every slice starts on a char boundary.
+pub fn comments(source: &str) -> Vec<(Span, &str)> {
+    let next_len = |i: usize| source[i..].chars().next().map_or(0, char::len_utf8);
+    let mut comments = vec![];
+    let mut i = 0;
+    while i < source.len() {
+        let rest = &source[i..];
+        let end = if rest.starts_with("//") {
+            i + rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            i + rest.find("*/").map(|end| end + 2).unwrap_or(rest.len())
+        } else {
+            if rest.starts_with('"') {
+                i += 1;
+                while i < source.len() && !source[i..].starts_with('"') {
+                    if source[i..].starts_with('\\') {
+                        i += 1;
+                    }
+                    i += next_len(i);
+                }
+            }
+            i += next_len(i).max(1);
+            continue;
+        };
+        comments.push((Span::new(i, end), &source[i..end]));
+        i = end;
+    }
+    comments
+}
+
+// Newlines don't end expressions inside brackets.
+fn bracketed<'source, T, F: FnOnce(&mut ParseState<'source>) -> T>(
+    state: &mut ParseState<'source>,
+    f: F,
+) -> T {
+    let outer = std::mem::replace(&mut state.newline_ends_expr, false);
+    let res = f(state);
+    state.newline_ends_expr = outer;
+    res
+}
+
 fn tag(raw: &str) -> impl Fn(&str) -> SResult<&str> + '_ {
-    // TODO: Consider only ignoring some whitespace...
     move |input: &str| {
-        let (input, _) = multispace0::<&str, SteelErr>(input)?;
+        let (input, _) = trivia(input)?;
         raw_tag::<&str, &str, SteelErr>(raw)(input)
             .map_err(|e| SteelErr::ErrorExpected(Box::new(e.into()), raw.to_string()).into())
     }
 }
 
-pub fn number_i64_raw(input: &str) -> SResult<i64> {
+pub fn number_i64_raw(input: &str) -> SResult<'_, i64> {
     let (input, sign) = alt((tag("+"), tag("-"), tag("")))(input)?;
     let (input, value) = map_res(
         take_while1(&|c: char| c.is_ascii_digit()),
@@ -30,12 +127,162 @@ pub fn number_i64_raw(input: &str) -> SResult<i64> {
     Ok((input, if sign == "-" { -value } else { value }))
 }
 
-pub fn number_i64<'source, ID, E: Into<SteelErr>, C: NodeStore<ID, i64, E>>(
+fn digits(input: &str) -> usize {
+    input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len())
+}
+
+// e.g. `1.5`, `2e10` or `-1.5e-3` (numbers without a fraction or exponent are i64s).
+pub fn number_f64_raw(input: &str) -> SResult<'_, f64> {
+    let (rest, sign) = alt((tag("+"), tag("-"), tag("")))(input)?;
+    let not_a_float = || {
+        nom::Err::Error(SteelErr::MalformedExpression(
+            input.to_string(),
+            "a float".to_string(),
+        ))
+    };
+    let mut len = digits(rest);
+    if len == 0 {
+        return Err(not_a_float());
+    }
+    let mut is_float = false;
+    if let Some(fraction) = rest[len..].strip_prefix('.') {
+        if digits(fraction) > 0 {
+            len += 1 + digits(fraction);
+            is_float = true;
+        }
+    }
+    if let Some(exponent) = rest[len..].strip_prefix(['e', 'E']) {
+        let sign_len = if exponent.starts_with(['+', '-']) {
+            1
+        } else {
+            0
+        };
+        let exponent_len = digits(&exponent[sign_len..]);
+        if exponent_len > 0 {
+            len += 1 + sign_len + exponent_len;
+            is_float = true;
+        }
+    }
+    if !is_float {
+        return Err(not_a_float());
+    }
+    let value: f64 = rest[..len].parse().map_err(|_| not_a_float())?;
+    if !value.is_finite() {
+        return Err(nom::Err::Error(SteelErr::MalformedExpression(
+            input.to_string(),
+            "a finite float".to_string(),
+        )));
+    }
+    Ok((&rest[len..], if sign == "-" { -value } else { value }))
+}
+
+fn add_spanned<T, C>(context: &mut C, value: T, span: Span) -> Result<C::ID, nom::Err<SteelErr>>
+where
+    C: CompilerContext + NodeStore<C::ID, T, C::E>,
+{
+    let id = context.add(value);
+    context
+        .set_span(id, span)
+        .map_err(|e| nom::Err::Error(e.into()))?;
+    Ok(id)
+}
+
+fn span_of<C: CompilerContext>(context: &C, id: C::ID) -> Result<Span, nom::Err<SteelErr>> {
+    context
+        .get_span(id)
+        .cloned()
+        .map_err(|e| nom::Err::Error(e.into()))
+}
+
+pub fn number_i64<'source, C: CompilerContext>(
     context: &mut C,
+    state: &mut ParseState<'source>,
     input: &'source str,
-) -> SResult<'source, ID> {
-    let (input, value) = number_i64_raw(input)?;
-    let id = context.add(value);
+) -> SResult<'source, C::ID> {
+    let (start, _) = trivia(input)?;
+    let (input, value) = number_i64_raw(start)?;
+    let id = add_spanned(context, value, Span::between(state.source, start, input))?;
+    Ok((input, id))
+}
+
+// e.g. `"hello\n"` (the input must start with the opening quote).
+pub fn string_raw(input: &str) -> SResult<'_, String> {
+    let (mut rest, _) = raw_tag::<&str, &str, SteelErr>("\"")(input)?;
+    let mut value = String::new();
+    loop {
+        let mut chars = rest.chars();
+        let c = if let Some(c) = chars.next() {
+            c
+        } else {
+            return Err(nom::Err::Error(SteelErr::MalformedExpression(
+                input.to_string(),
+                "a closing quote for the string".to_string(),
+            )));
+        };
+        match c {
+            '"' => return Ok((chars.as_str(), value)),
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    _ => {
+                        return Err(nom::Err::Error(SteelErr::MalformedExpression(
+                            rest.to_string(),
+                            "an escape sequence (one of \\n \\t \\r \\0 \\\\ \\\")".to_string(),
+                        )))
+                    }
+                };
+                value.push(escaped);
+            }
+            c => value.push(c),
+        }
+        rest = chars.as_str();
+    }
+}
+
+pub fn string_literal<'source, C: CompilerContext>(
+    context: &mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+) -> SResult<'source, C::ID> {
+    let (start, _) = trivia(input)?;
+    let (input, value) = string_raw(start)?;
+    let span = Span::between(state.source, start, input);
+    let id = add_spanned(context, StrLiteral::new(&value), span)?;
+    Ok((input, id))
+}
+
+// `true` or `false`.
+fn bool_literal<'source, C: CompilerContext>(
+    context: &mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+) -> SResult<'source, C::ID> {
+    let (start, _) = trivia(input)?;
+    let (input, value) = if let Ok((input, _)) = keyword("true")(start) {
+        (input, true)
+    } else {
+        let (input, _) = keyword("false")(start)?;
+        (input, false)
+    };
+    let id = add_spanned(context, value, Span::between(state.source, start, input))?;
+    Ok((input, id))
+}
+
+pub fn number_f64<'source, C: CompilerContext>(
+    context: &mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+) -> SResult<'source, C::ID> {
+    let (start, _) = trivia(input)?;
+    let (input, value) = number_f64_raw(start)?;
+    let id = add_spanned(context, value, Span::between(state.source, start, input))?;
     Ok((input, id))
 }
 
@@ -43,182 +290,463 @@ fn is_identifier_char(c: char) -> bool {
     c.is_alphanumeric() || (c == '_')
 }
 
-fn identifier_head(input: &str) -> SResult<&str> {
+fn identifier_head(input: &str) -> SResult<'_, &str> {
     alt((alpha1, tag("_")))(input)
 }
 
-fn identifier_tail(input: &str) -> SResult<&str> {
+fn identifier_tail(input: &str) -> SResult<'_, &str> {
     take_while(is_identifier_char)(input)
 }
 
-pub fn symbol_raw(og_input: &str) -> SResult<Symbol> {
+pub fn symbol_raw(og_input: &str) -> SResult<'_, Symbol> {
     let (input, (head, tail)) = tuple((identifier_head, identifier_tail))(og_input)?;
 
     let name = &og_input[0..head.len() + tail.len()];
 
     Ok((input, Symbol::new(name)))
 }
+fn keyword(word: &str) -> impl Fn(&str) -> SResult<'_, &str> + '_ {
+    move |input: &str| {
+        let (input, _) = trivia(input)?;
+        match symbol_raw(input) {
+            Ok((rest, symbol)) if symbol.name == word => Ok((rest, &input[..word.len()])),
+            _ => Err(nom::Err::Error(SteelErr::MalformedExpression(
+                input.to_string(),
+                format!("keyword {:?}", word),
+            ))),
+        }
+    }
+}
+
 pub fn binding<'source, ID, E: Into<SteelErr>, C: NodeStore<ID, Symbol, E>>(
     _context: &mut C,
     input: &'source str,
 ) -> SResult<'source, String> {
-    let (og_input, _) = multispace0(input)?;
+    let (og_input, _) = trivia(input)?;
     let (input, (head, tail)) = tuple((identifier_head, identifier_tail))(og_input)?;
     let name = &og_input[0..head.len() + tail.len()];
-    let (input, _) = multispace0(input)?;
+    let (input, _) = trivia(input)?;
     let (input, _) = tag("=")(input)?;
+    if input.starts_with('=') {
+        // This is a comparison e.g. `x==1`.
+        return Err(nom::Err::Error(SteelErr::MalformedExpression(
+            input.to_string(),
+            "a binding".to_string(),
+        )));
+    }
     Ok((input, name.to_string()))
 }
 
-pub fn symbol<'source, ID, E: Into<SteelErr>, C: NodeStore<ID, Symbol, E>>(
+pub fn symbol<'source, C: CompilerContext>(
     context: &mut C,
+    state: &mut ParseState<'source>,
     input: &'source str,
-) -> SResult<'source, ID> {
-    let (input, _) = multispace0(input)?;
-    let (input, symbol) = symbol_raw(input)?;
-    let id = context.add(symbol);
+) -> SResult<'source, C::ID> {
+    let (start, _) = trivia(input)?;
+    let (input, symbol) = symbol_raw(start)?;
+    let id = add_spanned(context, symbol, Span::between(state.source, start, input))?;
     Ok((input, id))
 }
 
-type Precedence = i32;
-const MIN_PRECENDENCE: Precedence = Precedence::MIN;
-const INIT_PRECENDENCE: Precedence = MIN_PRECENDENCE;
-const MUL_PRECENDENCE: Precedence = 2;
-const PLUS_PRECENDENCE: Precedence = 1;
-
 pub fn operator_raw<'source>(
+    operators: &OperatorTable,
     input: &'source str,
-    min_prec: &mut Precedence, // TODO: reject operators of the wrong prec...
-) -> SResult<'source, Operator> {
-    let ch = input.chars().next();
-    use Operator::*;
-    let (precendence, op) = match ch {
-        Some('+') => (PLUS_PRECENDENCE, Add),
-        Some('-') => (PLUS_PRECENDENCE, Sub),
-        Some('*') => (MUL_PRECENDENCE, Mul),
-        Some('/') => (MUL_PRECENDENCE, Div),
-        _ => {
-            return Err(nom::Err::Error(SteelErr::MalformedExpression(
-                input.to_string(),
-                "operator".to_string(),
-            )))
-        }
-    };
-    if precendence < *min_prec {
-        return Err(nom::Err::Error(SteelErr::PrecedenceError { precendence }));
+    fixity: Fixity,
+) -> SResult<'source, OperatorInfo> {
+    if let Some(info) = operators.lookup(input, fixity) {
+        Ok((&input[info.symbol.len()..], info.clone()))
+    } else {
+        Err(nom::Err::Error(SteelErr::MalformedExpression(
+            input.to_string(),
+            "operator".to_string(),
+        )))
     }
-    *min_prec = precendence; // otherwise raise the precendence.
-    Ok((&input[1..], op))
 }
 
-pub fn operator<'source, ID, E: Into<SteelErr>, C: NodeStore<ID, Operator, E>>(
+// Builtin operators get their own nodes, others are called by name.
+fn operator<'source, C: CompilerContext>(
     context: &mut C,
+    state: &mut ParseState<'source>,
     input: &'source str,
-    min_prec: &mut Precedence,
-) -> SResult<'source, ID> {
-    let (input, operator) = operator_raw(input, min_prec)?;
-    let id = context.add(operator);
-    Ok((input, id))
+    fixity: Fixity,
+) -> SResult<'source, (C::ID, OperatorInfo)> {
+    let (start, _) = trivia(input)?;
+    let (input, info) = operator_raw(&state.operators, start, fixity)?;
+    let span = Span::between(state.source, start, input);
+    let id = if let Some(op) = info.builtin() {
+        add_spanned(context, op, span)?
+    } else {
+        add_spanned(context, Symbol::new(&info.symbol), span)?
+    };
+    Ok((input, (id, info)))
 }
 
 type ArgBindings<ID> = Vec<(String, ID)>;
 
 fn args<'source, C: CompilerContext>(
     context: &mut C,
+    state: &mut ParseState<'source>,
     input: &'source str,
 ) -> SResult<'source, ArgBindings<C::ID>>
 where
     <C as CompilerContext>::E: Into<SteelErr>,
 {
-    let (input, _) = tag("(")(input)?;
+    bracketed(state, |state| args_impl(context, state, input))
+}
+
+fn args_impl<'source, C: CompilerContext>(
+    context: &mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+) -> SResult<'source, ArgBindings<C::ID>>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    let (mut input, _) = tag("(")(input)?;
+    let mut args = vec![];
+    if let Ok((input, _)) = tag(")")(input) {
+        return Ok((input, args));
+    }
     let mut arg_num = 0;
-    let (input, args) = separated_list0(tag(","), |input| {
-        let (input, name) = if let Ok((input, sym)) = binding(context, input) {
+    loop {
+        let (value_input, name) = if let Ok((input, sym)) = binding(context, input) {
             (input, sym)
         } else {
             let res = (input, format!("arg_{}", arg_num));
             arg_num += 1;
             res
         };
-        let mut ignore_prec = INIT_PRECENDENCE;
-        let (input, value) = expr(context, input, &mut ignore_prec)?;
-        Ok((input, (name, value)))
-    })(input)?;
-    let (input, _) = tag(")")(input)?;
-    Ok((input, args))
+        let (rest, value) = match expr(context, state, value_input, MIN_PRECEDENCE) {
+            Ok(res) => res,
+            Err(_err) if state.recovering => {
+                // Report the argument as a whole, rather than where in it the parser gave up.
+                let (start, _) = trivia(value_input)?;
+                let (skipped, _rest) = synchronise(start);
+                let skipped = skipped.trim_end();
+                let err =
+                    SteelErr::MalformedExpression(skipped.to_string(), "an argument".to_string())
+                        .at(Span::between(state.source, start, &start[skipped.len()..]));
+                recover(context, state, value_input, nom::Err::Error(err))?
+            }
+            Err(err) => return Err(err),
+        };
+        args.push((name, value));
+        if let Ok((rest, _)) = tag(",")(rest) {
+            input = rest;
+            continue;
+        }
+        match tag(")")(rest) {
+            Ok((rest, _)) => return Ok((rest, args)),
+            Err(err) if state.recovering => {
+                // Drop whatever is left of this argument.
+                state.errors.push(err.into());
+                let (_skipped, rest) = synchronise(rest);
+                if let Ok((rest, _)) = tag(",")(rest) {
+                    input = rest;
+                    continue;
+                }
+                let rest = tag(")")(rest).map(|(rest, _)| rest).unwrap_or(rest);
+                return Ok((rest, args));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// Skip to the next `,` or `)` that isn't nested in parentheses.
+fn synchronise(input: &str) -> (&str, &str) {
+    let mut depth = 0;
+    for (i, c) in input.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' | ',' if depth == 0 => return (&input[..i], &input[i..]),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    (input, "")
 }
 
+// Replace the malformed input with an `Invalid` node, if recovering from errors.
+fn recover<'source, C: CompilerContext>(
+    context: &mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+    err: nom::Err<SteelErr>,
+) -> SResult<'source, C::ID> {
+    if !state.recovering {
+        return Err(err);
+    }
+    let err: SteelErr = err.into();
+    let (start, _) = trivia(input)?;
+    let (skipped, rest) = synchronise(start);
+    // The node has its own span, so the message leaves out the error's location.
+    let message = match &err {
+        SteelErr::Located(err, _span) => format!("{}", err),
+        err => format!("{}", err),
+    };
+    let invalid = Invalid::new(skipped.trim_end(), &message);
+    let id = add_spanned(context, invalid, Span::between(state.source, start, rest))?;
+    state.errors.push(err);
+    Ok((rest, id))
+}
+
+// Continue the expression on the `left` with a call, postfix or infix operator (binding at least as
+// tightly as `min_prec`), or return `None` if the expression ends here.
 fn led<'source, C: CompilerContext>(
     context: &mut C,
+    state: &mut ParseState<'source>,
     left: C::ID,
     input: &'source str,
-    min_prec: &mut Precedence,
-) -> SResult<'source, C::ID>
+    min_prec: Precedence,
+    non_assoc: &mut Option<Precedence>,
+) -> SResult<'source, Option<C::ID>>
 where
     <C as CompilerContext>::E: Into<SteelErr>,
 {
+    let left_span = span_of(context, left)?;
+    let (trimmed, skipped) = trivia(input)?;
+    if state.newline_ends_expr && skipped.contains('\n') {
+        return Ok((input, None));
+    }
     // Unified calling syntax e.g. <expr>(...args...).
-    if let Ok((input, args)) = args(context, input) {
-        let call = context.add(Call::new(left, args));
-        return Ok((input, call));
-    }
-    let (input, op) = operator(context, input, min_prec)?;
-    let (input, right) = expr(context, input, min_prec)?;
-    let call = context.add(Call::new(
-        op,
-        vec![("arg_0".to_string(), left), ("arg_1".to_string(), right)],
-    ));
-    Ok((input, call))
+    if let Ok((input, args)) = args(context, state, input) {
+        let span = left_span.join(&Span::between(state.source, input, input));
+        let call = add_spanned(context, Call::new(left, args), span)?;
+        return Ok((input, Some(call)));
+    }
+    let infix = state.operators.lookup(trimmed, Fixity::Infix);
+    let postfix = state.operators.lookup(trimmed, Fixity::Postfix);
+    let info = match (infix, postfix) {
+        (Some(infix), Some(postfix)) if postfix.symbol.len() > infix.symbol.len() => postfix,
+        (Some(infix), _) => infix,
+        (None, Some(postfix)) => postfix,
+        (None, None) => return Ok((input, None)),
+    };
+    if info.precedence < min_prec {
+        // Leave the operator to an outer expression.
+        return Ok((input, None));
+    }
+    if *non_assoc == Some(info.precedence) && info.assoc == Assoc::Non {
+        return Err(nom::Err::Error(SteelErr::MalformedExpression(
+            trimmed.to_string(),
+            format!("parentheses as {:?} is non-associative", info.symbol),
+        )));
+    }
+    let fixity = info.fixity;
+    let (rest, (op, info)) = operator(context, state, input, fixity)?;
+    *non_assoc = Some(info.precedence).filter(|_| info.assoc == Assoc::Non);
+    let op_span = span_of(context, op)?;
+    let (input, args) = if fixity == Fixity::Postfix {
+        (rest, vec![("arg_0".to_string(), left)])
+    } else {
+        let (input, right) = match expr(context, state, rest, info.right_precedence()) {
+            Ok(res) => res,
+            Err(err) => recover(context, state, rest, err)?,
+        };
+        let args = vec![("arg_0".to_string(), left), ("arg_1".to_string(), right)];
+        (input, args)
+    };
+    let last = args.last().map(|(_, arg)| *arg).unwrap_or(op);
+    let span = left_span.join(&op_span).join(&span_of(context, last)?);
+    let call = add_spanned(context, Call::new(op, args), span)?;
+    Ok((input, Some(call)))
+}
+
+// e.g. `let x = 3; x*x`.
+fn let_binding<'source, C: CompilerContext>(
+    context: &mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+) -> SResult<'source, C::ID>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    let (start, _) = trivia(input)?;
+    let (input, _) = keyword("let")(start)?;
+    let (input, name) = binding(context, input)?;
+    let outer = std::mem::replace(&mut state.newline_ends_expr, true);
+    let value = expr(context, state, input, MIN_PRECEDENCE);
+    state.newline_ends_expr = outer;
+    let (input, value) = value?;
+    // The value ends with a `;` or a new line.
+    let (after_trivia, skipped) = trivia(input)?;
+    let input = if let Some(input) = after_trivia.strip_prefix(';') {
+        input
+    } else if skipped.contains('\n') {
+        after_trivia
+    } else {
+        tag(";")(input)?.0
+    };
+    let (input, body) = expr(context, state, input, MIN_PRECEDENCE)?;
+    let span = Span::between(state.source, start, start).join(&span_of(context, body)?);
+    let id = add_spanned(context, Let::new(&name, value, body), span)?;
+    Ok((input, id))
+}
+
+// e.g. `if x < 3 then 1 else 2`.
+fn conditional<'source, C: CompilerContext>(
+    context: &mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+) -> SResult<'source, C::ID>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    let (start, _) = trivia(input)?;
+    let (input, _) = keyword("if")(start)?;
+    let (input, condition) = expr(context, state, input, MIN_PRECEDENCE)?;
+    let (input, _) = keyword("then")(input)?;
+    let (input, then) = expr(context, state, input, MIN_PRECEDENCE)?;
+    let (input, _) = keyword("else")(input)?;
+    let (input, otherwise) = expr(context, state, input, MIN_PRECEDENCE)?;
+    let span = Span::between(state.source, start, start).join(&span_of(context, otherwise)?);
+    let id = add_spanned(context, If::new(condition, then, otherwise), span)?;
+    Ok((input, id))
+}
+
+fn params(input: &str) -> SResult<'_, Vec<String>> {
+    let (input, _) = tag("(")(input)?;
+    let (input, params) = separated_list0(tag(","), |input| {
+        let (input, _) = trivia(input)?;
+        let (input, symbol) = symbol_raw(input)?;
+        Ok((input, symbol.name))
+    })(input)?;
+    let (input, _) = tag(")")(input)?;
+    let (input, _) = tag("=>")(input)?;
+    Ok((input, params))
+}
+
+// e.g. `(x, y) => x+y`.
+fn lambda<'source, C: CompilerContext>(
+    context: &mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+) -> SResult<'source, C::ID>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    let (start, _) = trivia(input)?;
+    let (input, params) = params(start)?;
+    let (input, body) = expr(context, state, input, MIN_PRECEDENCE)?;
+    let span = Span::between(state.source, start, start).join(&span_of(context, body)?);
+    let id = add_spanned(context, Lambda::new(params, body), span)?;
+    Ok((input, id))
+}
+
+// Whether an operator is being used as a value e.g. `f(op=+)` or `+(1, 2)`.
+fn is_operator_value(input: &str) -> bool {
+    input.is_empty() || input.starts_with(['(', ')', ',', ';'])
+}
+
+// A prefix operator call e.g. `-x` or `!(a&&b)`, an operator used as a value, or `None` if
+// there is no operator here.
+fn prefix<'source, C: CompilerContext>(
+    context: &mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+) -> SResult<'source, Option<C::ID>>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    let (trimmed, _) = trivia(input)?;
+    let prefix = state.operators.lookup(trimmed, Fixity::Prefix).cloned();
+    let infix = state.operators.lookup(trimmed, Fixity::Infix).cloned();
+    let info = if let Some(info) = infix.as_ref().or(prefix.as_ref()) {
+        info
+    } else {
+        return Ok((input, None));
+    };
+    let (after, _) = trivia(&trimmed[info.symbol.len()..])?;
+    if let Some(prefix) = &prefix {
+        let operand = &trimmed[prefix.symbol.len()..];
+        match expr(context, state, operand, prefix.right_precedence()) {
+            Ok((input, operand)) => {
+                let (_, (op, _)) = operator(context, state, trimmed, Fixity::Prefix)?;
+                let span = span_of(context, op)?.join(&span_of(context, operand)?);
+                let args = vec![("arg_0".to_string(), operand)];
+                let call = add_spanned(context, Call::new(op, args), span)?;
+                return Ok((input, Some(call)));
+            }
+            Err(err) if !is_operator_value(after) => return Err(err),
+            Err(_) => {}
+        }
+    }
+    if !is_operator_value(after) {
+        let prefixes: Vec<String> = state
+            .operators
+            .prefixes()
+            .map(|symbol| format!("{:?}", symbol))
+            .collect();
+        return Err(nom::Err::Error(SteelErr::MalformedExpression(
+            trimmed.to_string(),
+            format!("a prefix operator (one of {})", prefixes.join(", ")),
+        )));
+    }
+    // Prefer the infix operator e.g. `f(op=-)` is subtraction.
+    let (input, (op, _)) = operator(context, state, trimmed, info.fixity)?;
+    Ok((input, Some(op)))
 }
 
-fn nud<'source, C: CompilerContext>(context: &mut C, input: &'source str) -> SResult<'source, C::ID>
+fn nud<'source, C: CompilerContext>(
+    context: &mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+) -> SResult<'source, C::ID>
 where
     <C as CompilerContext>::E: Into<SteelErr>,
 {
+    if params(input).is_ok() {
+        return lambda(context, state, input);
+    }
     if let Ok((input, _)) = tag("(")(input) {
-        let mut ignore_prec = INIT_PRECENDENCE;
-        let (input, wrapped) = expr(context, input, &mut ignore_prec)?;
+        let (input, wrapped) =
+            bracketed(state, |state| expr(context, state, input, MIN_PRECEDENCE))?;
         // TODO: handle larger expressions (before ')' )
         let (input, _) = tag(")")(input)?;
         return Ok((input, wrapped));
     }
-    if let Ok((input, sym)) = symbol(context, input) {
-        if let Ok((input, args)) = args(context, input) {
+    if keyword("let")(input).is_ok() {
+        return let_binding(context, state, input);
+    }
+    if keyword("if")(input).is_ok() {
+        return conditional(context, state, input);
+    }
+    if keyword("true")(input).is_ok() || keyword("false")(input).is_ok() {
+        return bool_literal(context, state, input);
+    }
+    if trivia(input)?.0.starts_with('"') {
+        return string_literal(context, state, input);
+    }
+    if let Ok((input, sym)) = symbol(context, state, input) {
+        if let Ok((input, args)) = args(context, state, input) {
             // Function call
-            let call = context.add(Call::new(sym, args));
+            let span = span_of(context, sym)?.join(&Span::between(state.source, input, input));
+            let call = add_spanned(context, Call::new(sym, args), span)?;
             return Ok((input, call));
         }
         return Ok((input, sym));
     }
-    let mut ignore_prec = INIT_PRECENDENCE;
-    if let Ok((input, op)) = operator(context, input, &mut ignore_prec) {
-        // Prefix operator e.g. -3.
-        let mut ignore_prec = INIT_PRECENDENCE;
-        if let Ok((input, right)) = expr(context, input, &mut ignore_prec) {
-            let z = context.add(0);
-            let call = context.add(Call::new(
-                op,
-                vec![("arg_0".to_string(), z), ("arg_1".to_string(), right)],
-            ));
-            return Ok((input, call));
-        }
-        // Operator expression e.g. f=+.
-        return Ok((input, op));
+    // Numbers include their sign (so that e.g. `-3` isn't a call to `neg`).
+    match number_f64(context, state, input) {
+        Ok(res) => return Ok(res),
+        Err(nom::Err::Error(SteelErr::MalformedExpression(_, expected)))
+            if expected == "a float" => {}
+        Err(err) => return Err(err), // e.g. the float was too large.
     }
-    // Otherwise expect a number
-    if let Ok(res) = number_i64(context, input) {
+    if let Ok(res) = number_i64(context, state, input) {
         return Ok(res);
     }
+    if let (input, Some(id)) = prefix(context, state, input)? {
+        return Ok((input, id));
+    }
+    let (input, _) = trivia(input)?;
     if input.is_empty() {
         Err(nom::Err::Error(SteelErr::UnexpectedEndOfInput))
     } else {
-        let mut report = input;
-        let mut lines = input.split('\n');
-        if let Some(first_line) = lines.next() {
-            report = first_line;
-        }
+        // Keep the rest of the input so that diagnostics can find the location.
         Err(nom::Err::Error(SteelErr::MalformedExpression(
-            report.to_string(),
+            input.to_string(),
             "the end of the input".to_string(),
         )))
     }
@@ -226,20 +754,23 @@ where
 
 pub fn expr<'context, 'source: 'context, C: CompilerContext>(
     context: &'context mut C,
+    state: &mut ParseState<'source>,
     input: &'source str,
-    min_prec: &mut Precedence,
+    min_prec: Precedence,
 ) -> SResult<'source, C::ID>
 where
     <C as CompilerContext>::E: Into<SteelErr>,
 {
-    let mut state = nud(context, input)?;
+    let (mut input, mut left) = nud(context, state, input)?;
+    // Non-associative operators (e.g. `==`) can't be chained without parentheses.
+    let mut non_assoc = None;
     loop {
-        let update = led(context, state.1, state.0, min_prec);
-        match update {
-            Ok(new_state) => {
-                state = new_state;
+        match led(context, state, left, input, min_prec, &mut non_assoc)? {
+            (rest, Some(new_left)) => {
+                input = rest;
+                left = new_left;
             }
-            Err(_) => return Ok(state),
+            (rest, None) => return Ok((rest, left)),
         }
     }
 }
@@ -251,32 +782,183 @@ pub fn program<'context, 'source: 'context, C: CompilerContext>(
 where
     <C as CompilerContext>::E: Into<SteelErr>,
 {
-    let mut min_prec = INIT_PRECENDENCE;
-    let (mut input, mut left) = expr(context, input, &mut min_prec)?;
-    loop {
-        let (new_input, _) = multispace0(input)?;
-        input = new_input;
-        if input.is_empty() {
-            return Ok((input, left));
-        }
-        match led(context, left, input, &mut min_prec) {
-            Ok((new_input, new_left)) => {
-                input = new_input;
-                left = new_left;
-            }
-            Err(nom::Err::Error(SteelErr::PrecedenceError { precendence })) => {
-                // go back down and try again
-                min_prec = precendence;
+    program_with_operators(context, input, &OperatorTable::default())
+}
+
+// Parse the program with an embedder's own operators.
+pub fn program_with_operators<'context, 'source: 'context, C: CompilerContext>(
+    context: &'context mut C,
+    input: &'source str,
+    operators: &OperatorTable,
+) -> SResult<'source, C::ID>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    let mut state = ParseState::new(input).with_operators(operators.clone());
+    program_impl(context, &mut state, input)
+}
+
+// Parse the program, keeping its comments so that they can be pretty printed.
+pub fn program_with_trivia<'context, 'source: 'context, C: CompilerContext>(
+    context: &'context mut C,
+    input: &'source str,
+) -> SResult<'source, C::ID>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    let (rest, root) = program(context, input)?;
+    attach_trivia(context, input, root).map_err(|e| nom::Err::Error(e.into()))?;
+    Ok((rest, root))
+}
+
+// Attach each comment to the outermost node that starts after it (or the end of the program).
+fn attach_trivia<C: CompilerContext>(
+    context: &mut C,
+    source: &str,
+    root: C::ID,
+) -> Result<(), C::E> {
+    let mut starts = vec![];
+    context.walk(root, &mut |context, id| {
+        // Operators are printed between their arguments, so avoid attaching comments to them.
+        if context.get_operator(id).is_err() {
+            if let Ok(span) = context.get_span(id) {
+                starts.push((*span, id));
             }
-            Err(e) => return Err(e),
         }
+    });
+    let mut trivia: HashMap<C::ID, Trivia> = HashMap::new();
+    for (span, comment) in comments(source) {
+        let next = starts
+            .iter()
+            .filter(|(start, _id)| start.start >= span.end)
+            .min_by_key(|(start, _id)| (start.start, std::cmp::Reverse(start.end)));
+        if let Some((_start, id)) = next {
+            let entry = trivia.entry(*id).or_default();
+            entry.leading.push(comment.to_string());
+        } else {
+            let entry = trivia.entry(root).or_default();
+            entry.trailing.push(comment.to_string());
+        }
+    }
+    for (id, trivia) in trivia {
+        context.set_trivia(id, trivia)?;
+    }
+    Ok(())
+}
+
+// Parse as much of the program as possible, returning every error found along the way.
+pub fn program_recovering<C: CompilerContext>(
+    context: &mut C,
+    input: &str,
+) -> (Option<C::ID>, Vec<SteelErr>)
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    let mut state = ParseState::new(input).recovering();
+    let root = match program_impl(context, &mut state, input) {
+        Ok((_input, root)) => Some(root),
+        Err(err) => recover(context, &mut state, input, err)
+            .ok()
+            .map(|(_input, root)| root),
+    };
+    (root, state.errors)
+}
+
+fn program_impl<'context, 'source: 'context, C: CompilerContext>(
+    context: &'context mut C,
+    state: &mut ParseState<'source>,
+    input: &'source str,
+) -> SResult<'source, C::ID>
+where
+    <C as CompilerContext>::E: Into<SteelErr>,
+{
+    let (input, root) = expr(context, state, input, MIN_PRECEDENCE)?;
+    let (input, _) = trivia(input)?;
+    if input.is_empty() {
+        return Ok((input, root));
     }
+    let err = nom::Err::Error(SteelErr::MalformedExpression(
+        input.to_string(),
+        "operator".to_string(),
+    ));
+    if state.recovering {
+        // Keep what has been parsed so far.
+        state.errors.push(err.into());
+        return Ok(("", root));
+    }
+    Err(err)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::assert_err_is;
+    use crate::nodes::Operator;
+
+    #[test]
+    fn parse_trivia() {
+        assert_eq!(trivia("  x").unwrap(), ("x", "  "));
+        assert_eq!(
+            trivia(" // a\n /* b */x // c").unwrap(),
+            ("x // c", " // a\n /* b */")
+        );
+        assert_eq!(trivia("// a").unwrap(), ("", "// a"));
+        assert_err_is!(
+            trivia("/* a"),
+            "Parsing Error: Expected the end of the block comment, found \"/* a\""
+        );
+    }
+
+    #[test]
+    fn find_comments() {
+        let source = "f(\"// not\\\" /* a comment */\", /* b */ 1) // c\nx";
+        let found: Vec<&str> = comments(source).into_iter().map(|(_, c)| c).collect();
+        assert_eq!(found, vec!["/* b */", "// c"]);
+    }
+
+    #[test]
+    fn handles_non_ascii_text() {
+        let source = "f(\"é\\ü // not\", /* ñ */ 1) // 日本\nx";
+        let found: Vec<&str> = comments(source).into_iter().map(|(_, c)| c).collect();
+        assert_eq!(found, vec!["/* ñ */", "// 日本"]);
+        let mut store = crate::ast::Ast::new();
+        let (_, root) = program_with_trivia(&mut store, "héllo").unwrap();
+        assert_eq!(store.pretty(root), "héllo");
+        let (_root, errors) = program_recovering(&mut crate::ast::Ast::new(), "f(é, 1)");
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        let source = "// héllo\nlen(\"日本\")+1 /* ü */";
+        let mut store = crate::ast::Ast::new();
+        let (_, root) = program_with_trivia(&mut store, source).unwrap();
+        assert_eq!(store.pretty(root), "// héllo\nlen(\"日本\")+1 /* ü */");
+    }
+
+    fn keeps_comments<C: CompilerContext>()
+    where
+        <C as CompilerContext>::E: Into<SteelErr>,
+    {
+        let source = "// header\nlet x = /* one */ 1\n// use x\nx+ // two\n2 // done";
+        let expected = "// header\nlet x = /* one */ 1; // use x\nx+// two\n2 // done";
+        let mut store = C::new();
+        let (_, root) = program_with_trivia(&mut store, source).unwrap();
+        assert_eq!(store.pretty(root), expected);
+        // The printed program keeps the same comments.
+        let mut store = C::new();
+        let (_, root) = program_with_trivia(&mut store, expected).unwrap();
+        assert_eq!(store.pretty(root), expected);
+        // Comments are dropped unless requested.
+        let mut store = C::new();
+        let (_, root) = program(&mut store, source).unwrap();
+        assert_eq!(store.pretty(root), "let x = 1; x+2");
+    }
+
+    #[test]
+    fn keeps_comments_ast() {
+        keeps_comments::<crate::ast::Ast>();
+    }
+
+    #[test]
+    fn keeps_comments_ecs() {
+        keeps_comments::<crate::ecs::Ecs>();
+    }
 
     #[test]
     fn parse_symbol() {
@@ -302,41 +984,99 @@ mod test {
     }
 
     #[test]
-    fn parse_operator() {
-        let mut prec = INIT_PRECENDENCE;
-        assert_eq!(operator_raw("+", &mut prec).unwrap(), ("", Operator::Add));
-        let mut prec = MUL_PRECENDENCE;
-        assert_eq!(operator_raw("*", &mut prec).unwrap(), ("", Operator::Mul));
-        let mut prec = PLUS_PRECENDENCE;
-        assert_eq!(operator_raw("*", &mut prec).unwrap(), ("", Operator::Mul));
-        let mut prec = INIT_PRECENDENCE;
-        assert_eq!(operator_raw("*", &mut prec).unwrap(), ("", Operator::Mul));
+    fn parse_string_with_escapes() {
+        assert_eq!(
+            string_raw(r#""a\"b\\c\nd" rest"#).unwrap(),
+            (" rest", "a\"b\\c\nd".to_string())
+        );
     }
 
     #[test]
-    fn parse_operator_in_wrong_precendence() {
-        let mut prec = MUL_PRECENDENCE;
+    fn parse_malformed_strings() {
+        assert_err_is!(
+            string_raw("\"abc"),
+            "Parsing Error: Expected a closing quote for the string, found \"\\\"abc\""
+        );
         assert_err_is!(
-            operator_raw("+", &mut prec),
-            format!(
-                "Parsing Error: Unexpected operator due to max precendence setting ({})",
-                PLUS_PRECENDENCE
-            ),
+            string_raw(r#""a\qb""#),
+            "Parsing Error: Expected an escape sequence (one of \\n \\t \\r \\0 \\\\ \\\"), found \"\\\\qb\\\"\""
         );
     }
 
+    #[test]
+    fn parse_operator() {
+        let table = OperatorTable::default();
+        let symbol = |input| {
+            let (rest, info) = operator_raw(&table, input, Fixity::Infix).unwrap();
+            (rest, info.symbol.clone(), info.builtin())
+        };
+        assert_eq!(symbol("+"), ("", "+".to_string(), Some(Operator::Add)));
+        assert_eq!(symbol("<=1"), ("1", "<=".to_string(), Some(Operator::Le)));
+        assert_eq!(symbol("!=1"), ("1", "!=".to_string(), Some(Operator::Ne)));
+        assert_eq!(symbol("*"), ("", "*".to_string(), Some(Operator::Mul)));
+    }
+
     #[test]
     fn parse_non_operator() {
-        let mut prec = INIT_PRECENDENCE;
+        let table = OperatorTable::default();
         assert_err_is!(
-            operator_raw("#lol", &mut prec),
+            operator_raw(&table, "#lol", Fixity::Infix),
             "Parsing Error: Expected operator, found \"#lol\""
         );
-        let mut prec = INIT_PRECENDENCE;
         assert_err_is!(
-            operator_raw("123", &mut prec),
+            operator_raw(&table, "123", Fixity::Infix),
             "Parsing Error: Expected operator, found \"123\""
         );
+        assert_err_is!(
+            operator_raw(&table, "!x", Fixity::Infix),
+            "Parsing Error: Expected operator, found \"!x\""
+        );
+    }
+
+    #[test]
+    fn parse_non_associative_chain() {
+        let mut store = crate::ast::Ast::new();
+        assert_err_is!(
+            program(&mut store, "a==b!=c"),
+            "Parsing Error: Expected parentheses as \"!=\" is non-associative, found \"!=c\""
+        );
+        let (_, root) = program(&mut store, "(a==b)!=c").unwrap();
+        assert_eq!(store.pretty(root), "(a==b)!=c");
+    }
+
+    fn parses_user_operators<C: CompilerContext>()
+    where
+        <C as CompilerContext>::E: Into<SteelErr>,
+    {
+        let table = OperatorTable::default()
+            .infix("**", 7, Assoc::Right)
+            .postfix("?", 8);
+        let round_trip = |source: &str| {
+            let mut store = C::new();
+            let (rest, root) = program_with_operators(&mut store, source, &table).unwrap();
+            assert_eq!(rest, "");
+            store.pretty_with(root, &table)
+        };
+        assert_eq!(round_trip("2**3**4"), "2**3**4");
+        assert_eq!(round_trip("(2**3)**4"), "(2**3)**4");
+        assert_eq!(round_trip("2*3**4+1"), "2*3**4+1");
+        assert_eq!(round_trip("(2*3)**4"), "(2*3)**4");
+        assert_eq!(round_trip("x?**y?"), "x?**y?");
+        assert_eq!(round_trip("(x+1)?"), "(x+1)?");
+        assert_eq!(round_trip("f(x)?(y)"), "f(x)?(y)");
+        // Without the table, `?` isn't an operator.
+        let mut store = C::new();
+        assert!(program(&mut store, "x?").is_err());
+    }
+
+    #[test]
+    fn parse_user_operators_ast() {
+        parses_user_operators::<crate::ast::Ast>();
+    }
+
+    #[test]
+    fn parse_user_operators_ecs() {
+        parses_user_operators::<crate::ecs::Ecs>();
     }
 
     #[test]
@@ -346,6 +1086,98 @@ mod test {
         assert_eq!(number_i64_raw("-1").unwrap(), ("", -1i64));
     }
 
+    fn spans_of_program<C: CompilerContext>(mut context: C) -> Result<(), SteelErr>
+    where
+        SteelErr: From<<C as CompilerContext>::E>,
+    {
+        let source = "foo(12, a)+\n  3";
+        let (_, root) = program(&mut context, source)?;
+        assert_eq!(context.get_span(root)?, &Span::new(0, source.len()));
+        let call = context.get_call(root)?.clone();
+        let left = call.left.expect("Should have a left argument");
+        let right = call.right.expect("Should have a right argument");
+        assert_eq!(context.get_span(call.callee)?.text(source), "+");
+        assert_eq!(context.get_span(left)?.text(source), "foo(12, a)");
+        assert_eq!(context.get_span(right)?.line_col(source), (2, 3));
+        let inner = context.get_call(left)?.clone();
+        assert_eq!(context.get_span(inner.callee)?.text(source), "foo");
+        assert_eq!(context.get_span(inner.args[0].1)?.text(source), "12");
+        assert_eq!(context.get_span(inner.args[1].1)?.text(source), "a");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_records_spans_ast() -> Result<(), SteelErr> {
+        spans_of_program(crate::ast::Ast::new())
+    }
+
+    #[test]
+    fn parse_records_spans_ecs() -> Result<(), SteelErr> {
+        spans_of_program(crate::ecs::Ecs::new())
+    }
+
+    fn recovers_from_errors<C: CompilerContext>(mut context: C) -> Result<(), SteelErr>
+    where
+        SteelErr: From<<C as CompilerContext>::E>,
+    {
+        let source = "f(1, #, g(2 3), 4";
+        assert!(program(&mut C::new(), source).is_err());
+        let (root, errors) = program_recovering(&mut context, source);
+        let root = root.expect("Should produce a partial tree");
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert_eq!(
+            format!("{}", errors[0]),
+            "Expected an argument, found \"#\" at 5..6"
+        );
+        assert_eq!(errors[0].span().map(|span| span.text(source)), Some("#"));
+        assert_eq!(context.pretty(root), "f(1, #, g(2), 4)");
+        let invalid = context.get_call(root)?.args[1].1;
+        assert_eq!(context.get_span(invalid)?.text(source), "#");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_recovering_ast() -> Result<(), SteelErr> {
+        recovers_from_errors(crate::ast::Ast::new())
+    }
+
+    #[test]
+    fn parse_recovering_ecs() -> Result<(), SteelErr> {
+        recovers_from_errors(crate::ecs::Ecs::new())
+    }
+
+    #[test]
+    fn parse_recovering_without_errors() {
+        let mut context = crate::ast::Ast::new();
+        let (root, errors) = program_recovering(&mut context, "f(1, g())+2");
+        assert_eq!(errors.len(), 0, "{:?}", errors);
+        assert_eq!(context.pretty(root.unwrap()), "f(1, g())+2");
+    }
+
+    #[test]
+    fn parse_number_f64() {
+        assert_eq!(number_f64_raw("1.5").unwrap(), ("", 1.5f64));
+        assert_eq!(number_f64_raw("-0.25+x").unwrap(), ("+x", -0.25f64));
+        assert_eq!(number_f64_raw("2e10").unwrap(), ("", 2e10f64));
+        assert_eq!(number_f64_raw("1.5E-3)").unwrap(), (")", 1.5e-3f64));
+    }
+
+    #[test]
+    fn parse_non_number_f64() {
+        assert_err_is!(
+            number_f64_raw("12"),
+            "Parsing Error: Expected a float, found \"12\""
+        );
+        assert_err_is!(
+            number_f64_raw("1.e5"),
+            "Parsing Error: Expected a float, found \"1.e5\""
+        );
+        assert_err_is!(
+            number_f64_raw("1e999"),
+            "Parsing Error: Expected a finite float, found \"1e999\""
+        );
+    }
+
     #[test]
     fn parse_non_number_i64() {
         assert_err_is!(
@@ -1,65 +1,231 @@
 use crate::compiler_context::CompilerContext;
+use crate::operators::{Fixity, OperatorInfo, OperatorTable, Precedence};
 
-pub fn pretty<C: CompilerContext + ?Sized>(context: &C, id: C::ID) -> String {
-    let (res, _complex_expr, is_operator) = pretty_impl(context, id);
-    if is_operator {
-        format!("({})", res)
+struct Printed {
+    text: String,
+    complex_expr: bool, // e.g. a `let` that needs parentheses around it when used as an operand.
+    is_operator: bool,
+    operator_call: Option<(Precedence, Fixity)>,
+}
+
+impl Printed {
+    fn new(text: String, complex_expr: bool, is_operator: bool) -> Self {
+        Self {
+            text,
+            complex_expr,
+            is_operator,
+            operator_call: None,
+        }
+    }
+}
+
+pub fn pretty<C: CompilerContext + ?Sized>(
+    context: &C,
+    id: C::ID,
+    operators: &OperatorTable,
+) -> String {
+    let printed = pretty_impl(context, id, operators);
+    if printed.is_operator {
+        format!("({})", printed.text)
     } else {
-        res
+        printed.text
     }
 }
 
-fn pretty_inner<C: CompilerContext + ?Sized>(context: &C, id: C::ID) -> String {
-    let (res, complex_expr, _is_operator) = pretty_impl(context, id);
-    if complex_expr {
-        format!("({})", res)
+// Print an operand of `op` (which is on the `left` or right), adding parentheses where needed.
+fn pretty_operand<C: CompilerContext + ?Sized>(
+    context: &C,
+    id: C::ID,
+    operators: &OperatorTable,
+    op: &OperatorInfo,
+    left: bool,
+) -> String {
+    let printed = pretty_impl(context, id, operators);
+    let needs_parens = match printed.operator_call {
+        Some((precedence, _fixity)) => op.needs_parens(precedence, left),
+        None => printed.complex_expr || printed.is_operator,
+    };
+    if needs_parens {
+        format!("({})", printed.text)
     } else {
-        res
+        printed.text
+    }
+}
+
+// Quote a string so that it parses back to the same value.
+fn escape(value: &str) -> String {
+    let mut res = "\"".to_string();
+    for c in value.chars() {
+        match c {
+            '\n' => res += "\\n",
+            '\t' => res += "\\t",
+            '\r' => res += "\\r",
+            '\0' => res += "\\0",
+            '\\' => res += "\\\\",
+            '"' => res += "\\\"",
+            c => res.push(c),
+        }
     }
+    res.push('"');
+    res
 }
 
-pub fn pretty_impl<C: CompilerContext + ?Sized>(context: &C, id: C::ID) -> (String, bool, bool) {
+fn pretty_impl<C: CompilerContext + ?Sized>(
+    context: &C,
+    id: C::ID,
+    operators: &OperatorTable,
+) -> Printed {
+    let printed = pretty_node(context, id, operators);
+    let trivia = if let Ok(trivia) = context.get_trivia(id) {
+        trivia
+    } else {
+        return printed;
+    };
+    let mut out = String::new();
+    for comment in &trivia.leading {
+        out += comment;
+        // Line comments need to end before the node.
+        out += if comment.starts_with("//") { "\n" } else { " " };
+    }
+    out += &printed.text;
+    for (i, comment) in trivia.trailing.iter().enumerate() {
+        out += if out.ends_with('\n') { "" } else { " " };
+        out += comment;
+        if comment.starts_with("//") && i + 1 < trivia.trailing.len() {
+            out += "\n";
+        }
+    }
+    Printed {
+        text: out,
+        ..printed
+    }
+}
+
+fn pretty_node<C: CompilerContext + ?Sized>(
+    context: &C,
+    id: C::ID,
+    operators: &OperatorTable,
+) -> Printed {
     if let Ok(v) = context.get_i64(id) {
-        return (format!("{}", v), *v < 0, false);
+        return Printed::new(format!("{}", v), *v < 0, false);
+    }
+    if let Ok(v) = context.get_f64(id) {
+        // Debug formatting always includes a `.` or exponent, so this parses back as an f64.
+        return Printed::new(format!("{:?}", v), v.is_sign_negative(), false);
+    }
+    if let Ok(v) = context.get_bool(id) {
+        return Printed::new(format!("{}", v), false, false);
+    }
+    if let Ok(s) = context.get_str(id) {
+        return Printed::new(escape(&s.value), false, false);
     }
     if let Ok(s) = context.get_operator(id) {
-        return (s.to_string(), false, true);
+        return Printed::new(s.to_string(), false, true);
     }
     if let Ok(s) = context.get_symbol(id) {
-        return (s.name.to_string(), false, false);
+        // User defined operators are called by name.
+        let is_operator = operators.contains(&s.name);
+        return Printed::new(s.name.to_string(), false, is_operator);
+    }
+    if let Ok(binding) = context.get_let(id) {
+        let text = format!(
+            "let {} = {}; {}",
+            binding.name,
+            pretty(context, binding.value, operators),
+            pretty(context, binding.body, operators)
+        );
+        return Printed::new(text, true, false);
+    }
+    if let Ok(lambda) = context.get_lambda(id) {
+        let text = format!(
+            "({}) => {}",
+            lambda.params.join(", "),
+            pretty(context, lambda.body, operators)
+        );
+        return Printed::new(text, true, false);
+    }
+    if let Ok(conditional) = context.get_if(id) {
+        let text = format!(
+            "if {} then {} else {}",
+            pretty(context, conditional.condition, operators),
+            pretty(context, conditional.then, operators),
+            pretty(context, conditional.otherwise, operators)
+        );
+        return Printed::new(text, true, false);
+    }
+    if let Ok(invalid) = context.get_invalid(id) {
+        return Printed::new(invalid.text.to_string(), false, false);
     }
     if let Ok(c) = context.get_call(id) {
-        let (mut callee, is_expr, is_op) = pretty_impl(context, c.callee);
-        let mut attempt_operator_call = true;
-        let mut arg_num = 0;
-        let args: Vec<String> = {
-            let attempt_operator_call = &mut attempt_operator_call;
-            c.args
-                .iter()
-                .map(|(name, arg)| {
-                    if name.starts_with("arg_") && name == &format!("arg_{}", arg_num) {
-                        arg_num += 1;
-                        pretty_inner(context, *arg)
+        let callee = pretty_impl(context, c.callee, operators);
+        let positional = |(arg_num, (name, _arg)): (usize, &(String, C::ID))| {
+            name == &format!("arg_{}", arg_num)
+        };
+        let info = if let Ok(op) = context.get_operator(c.callee) {
+            match (op.is_unary(), c.args.len()) {
+                (true, 1) => operators.find(op.to_str(), Fixity::Prefix),
+                (false, 2) => operators.find(op.to_str(), Fixity::Infix),
+                _ => None,
+            }
+        } else if let Ok(symbol) = context.get_symbol(c.callee) {
+            match c.args.len() {
+                1 => operators
+                    .find(&symbol.name, Fixity::Prefix)
+                    .or_else(|| operators.find(&symbol.name, Fixity::Postfix)),
+                2 => operators.find(&symbol.name, Fixity::Infix),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        let info = info.filter(|_| c.args.iter().enumerate().all(positional));
+        if let Some(info) = info {
+            let operand = |arg: usize, left: bool| {
+                pretty_operand(context, c.args[arg].1, operators, info, left)
+            };
+            let text = match info.fixity {
+                Fixity::Prefix => {
+                    let operand = operand(0, false);
+                    // e.g. `-(3)`, as `-3` would parse as a negative literal instead.
+                    if operand.starts_with(|c: char| c.is_ascii_digit()) {
+                        format!("{}({})", callee.text, operand)
                     } else {
-                        *attempt_operator_call = false;
-                        format!("{}={}", name, pretty(context, *arg))
+                        format!("{}{}", callee.text, operand)
                     }
-                })
-                .collect()
-        };
-        if is_op && !is_expr && attempt_operator_call {
-            let args = args.join(&callee);
-            return (
-                format!("{}{}", if c.args.len() < 2 { &callee } else { "" }, args),
-                true,
-                false,
-            );
-        }
-        if is_expr || is_op {
-            callee = format!("({})", callee);
+                }
+                Fixity::Postfix => format!("{}{}", operand(0, true), callee.text),
+                Fixity::Infix => {
+                    format!("{}{}{}", operand(0, true), callee.text, operand(1, false))
+                }
+            };
+            return Printed {
+                operator_call: Some((info.precedence, info.fixity)),
+                ..Printed::new(text, false, false)
+            };
         }
+        let mut arg_num = 0;
+        let args: Vec<String> = c
+            .args
+            .iter()
+            .map(|(name, arg)| {
+                if name == &format!("arg_{}", arg_num) {
+                    arg_num += 1;
+                    pretty_impl(context, *arg, operators).text
+                } else {
+                    format!("{}={}", name, pretty(context, *arg, operators))
+                }
+            })
+            .collect();
+        // Calls bind more tightly than operators (other than postfix ones e.g. `f?(x)`).
+        let operator_call =
+            matches!(callee.operator_call, Some((_, fixity)) if fixity != Fixity::Postfix);
+        let callee = if callee.complex_expr || callee.is_operator || operator_call {
+            format!("({})", callee.text)
+        } else {
+            callee.text
+        };
         let args = args.join(", ");
-        return (format!("{}({})", callee, args), false, false);
+        return Printed::new(format!("{}({})", callee, args), false, false);
     }
-    (format!("{{node? {:?}}}", id), false, true)
+    Printed::new(format!("{{node? {:?}}}", id), false, true)
 }
@@ -0,0 +1,229 @@
+use crate::{nodes::Call, CompilerContext};
+
+// Where a node hangs in the tree: its parent and which of the parent's children it is
+// (or None for the root).
+type Slot<ID> = Option<(ID, usize)>;
+
+// A change that makes the program smaller (or undoes one).
+enum Edit<ID> {
+    Replace(Slot<ID>, ID),
+    DropArg(ID, usize),
+    InsertArg(ID, usize, (String, ID)),
+}
+
+fn children<Ctx: CompilerContext>(store: &Ctx, id: Ctx::ID) -> Vec<Ctx::ID> {
+    if let Ok(call) = store.get_call(id) {
+        let mut children = vec![call.callee];
+        children.extend(call.args.iter().map(|(_name, arg)| *arg));
+        children
+    } else if let Ok(binding) = store.get_let(id) {
+        vec![binding.value, binding.body]
+    } else if let Ok(lambda) = store.get_lambda(id) {
+        vec![lambda.body]
+    } else if let Ok(conditional) = store.get_if(id) {
+        vec![
+            conditional.condition,
+            conditional.then,
+            conditional.otherwise,
+        ]
+    } else {
+        vec![]
+    }
+}
+
+fn set_child<Ctx: CompilerContext>(
+    store: &mut Ctx,
+    parent: Ctx::ID,
+    index: usize,
+    child: Ctx::ID,
+) -> Result<(), Ctx::E> {
+    if let Ok(call) = store.get_call_mut(parent) {
+        let mut args = call.args.clone();
+        let callee = if index == 0 {
+            child
+        } else {
+            args[index - 1].1 = child;
+            call.callee
+        };
+        *call = Call::new(callee, args); // Keeps `left` and `right` in sync with the args.
+    } else if let Ok(binding) = store.get_let_mut(parent) {
+        *[&mut binding.value, &mut binding.body][index] = child;
+    } else if let Ok(lambda) = store.get_lambda_mut(parent) {
+        lambda.body = child;
+    } else {
+        let conditional = store.get_if_mut(parent)?;
+        *[
+            &mut conditional.condition,
+            &mut conditional.then,
+            &mut conditional.otherwise,
+        ][index] = child;
+    }
+    Ok(())
+}
+
+// Calls, lets and ifs are replaced by their parts (e.g. a let by its body) or small constants,
+// outermost first, as that removes the most. Lambdas are only shrunk inside, as their bodies
+// rarely make sense without their params bound.
+fn edits<Ctx: CompilerContext>(
+    store: &Ctx,
+    slot: Slot<Ctx::ID>,
+    id: Ctx::ID,
+    constants: &[Ctx::ID],
+    edits: &mut Vec<Edit<Ctx::ID>>,
+) {
+    let parts = if let Ok(call) = store.get_call(id) {
+        call.args.iter().map(|(_name, arg)| *arg).collect()
+    } else if let Ok(binding) = store.get_let(id) {
+        vec![binding.body, binding.value]
+    } else if let Ok(conditional) = store.get_if(id) {
+        vec![conditional.then, conditional.otherwise]
+    } else {
+        vec![]
+    };
+    if !parts.is_empty() {
+        for part in parts {
+            edits.push(Edit::Replace(slot, part));
+        }
+        for constant in constants {
+            edits.push(Edit::Replace(slot, *constant));
+        }
+    }
+    if let Ok(call) = store.get_call(id) {
+        for (index, (name, _arg)) in call.args.iter().enumerate() {
+            if !name.starts_with("arg_") {
+                edits.push(Edit::DropArg(id, index));
+            }
+        }
+    }
+    for (index, child) in children(store, id).into_iter().enumerate() {
+        self::edits(store, Some((id, index)), child, constants, edits);
+    }
+}
+
+// Apply the edit (which may change the root), returning the edit that undoes it.
+fn apply<Ctx: CompilerContext>(
+    store: &mut Ctx,
+    root: &mut Ctx::ID,
+    edit: Edit<Ctx::ID>,
+) -> Result<Edit<Ctx::ID>, Ctx::E> {
+    Ok(match edit {
+        Edit::Replace(None, node) => Edit::Replace(None, std::mem::replace(root, node)),
+        Edit::Replace(Some((parent, index)), node) => {
+            let old = children(store, parent)[index];
+            set_child(store, parent, index, node)?;
+            Edit::Replace(Some((parent, index)), old)
+        }
+        Edit::DropArg(id, index) => {
+            let call = store.get_call_mut(id)?;
+            let mut args = call.args.clone();
+            let arg = args.remove(index);
+            *call = Call::new(call.callee, args);
+            Edit::InsertArg(id, index, arg)
+        }
+        Edit::InsertArg(id, index, arg) => {
+            let call = store.get_call_mut(id)?;
+            let mut args = call.args.clone();
+            args.insert(index, arg);
+            *call = Call::new(call.callee, args);
+            Edit::DropArg(id, index)
+        }
+    })
+}
+
+// Shrink a program for which `fails` holds (e.g. one that a test failed on) to a minimal one
+// for which it still holds, by replacing calls, lets and ifs with their parts or small constants
+// and by dropping named arguments. Returns the minimal program (pretty printed).
+// The program is edited in place, so `root` may no longer be the root afterwards.
+pub fn shrink<Ctx: CompilerContext, F: FnMut(&str) -> bool>(
+    store: &mut Ctx,
+    root: Ctx::ID,
+    mut fails: F,
+) -> Result<String, Ctx::E> {
+    let constants = [store.add(0i64), store.add(1i64)];
+    let mut root = root;
+    let mut program = store.pretty(root);
+    'shrinking: loop {
+        let mut candidates = vec![];
+        edits(store, None, root, &constants, &mut candidates);
+        for edit in candidates {
+            let undo = apply(store, &mut root, edit)?;
+            let candidate = store.pretty(root);
+            // Only accepting smaller programs guarantees that shrinking finishes.
+            if candidate.len() < program.len() && fails(&candidate) {
+                program = candidate;
+                continue 'shrinking;
+            }
+            apply(store, &mut root, undo)?;
+        }
+        return Ok(program);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gen_code::{generate_random_program, seed_from_env, Spec};
+    use crate::{ast::Ast, ecs::Ecs, parser::program, SteelErr};
+
+    fn shrinks<Ctx: CompilerContext>() -> Result<(), SteelErr>
+    where
+        SteelErr: From<<Ctx as CompilerContext>::E>,
+    {
+        let shrink_to = |source: &str, fails: &dyn Fn(&str) -> bool| -> Result<String, SteelErr> {
+            let mut store = Ctx::new();
+            let (_rest, root) = program(&mut store, source)?;
+            Ok(shrink(&mut store, root, fails)?)
+        };
+        let divides_by_zero = |program: &str| program.contains("/0");
+        assert_eq!(
+            shrink_to("putchar(3*(2+(1/0))-4)", &divides_by_zero)?,
+            "1/0"
+        );
+        assert_eq!(
+            shrink_to(
+                "let x = 2; if x > 1 then putchar(x/0) else 3",
+                &divides_by_zero
+            )?,
+            "x/0"
+        );
+        let has_named_arg = |program: &str| program.contains("x=");
+        assert_eq!(
+            shrink_to("((a, x) => a*x)(a=putchar(2), x=2+3)", &has_named_arg)?,
+            "((a, x) => a)(x=2)"
+        );
+        // Programs that don't fail are left alone.
+        assert_eq!(shrink_to("1+2", &|_| false)?, "1+2");
+        Ok(())
+    }
+
+    #[test]
+    fn shrinks_random_programs() {
+        for _run in 0..20 {
+            let mut store = Ast::new();
+            let spec = Spec::default().sized(50).seeded(seed_from_env());
+            let root = generate_random_program("shrinker", &mut store, &spec, &mut spec.rng());
+            let original = store.pretty(root);
+            let shrunk = shrink(&mut store, root, |program| program.contains('*')).unwrap();
+            assert!(
+                shrunk.len() <= original.len(),
+                "{} from {}",
+                shrunk,
+                original
+            );
+            if original.contains('*') {
+                assert!(shrunk.contains('*'), "{} from {}", shrunk, original);
+                assert!(shrunk.len() < 20, "{} from {}", shrunk, original);
+            }
+        }
+    }
+
+    #[test]
+    fn shrinks_ast() -> Result<(), SteelErr> {
+        shrinks::<Ast>()
+    }
+
+    #[test]
+    fn shrinks_ecs() -> Result<(), SteelErr> {
+        shrinks::<Ecs>()
+    }
+}
@@ -8,7 +8,6 @@ pub fn typed_descriptor<T>() -> String {
     name.to_string()
 }
 
-#[derive(PartialEq, Eq, Hash)]
 pub struct TypedIndex<T> {
     pub id: Index,
     pub ty: PhantomData<T>,
@@ -24,18 +23,28 @@ impl<T> TypedIndex<T> {
     pub fn new(id: Index) -> Self {
         Self {
             id,
-            ty: PhantomData::default(),
+            ty: PhantomData,
         }
     }
 }
 
+// Implemented by hand so that `T` doesn't need to be comparable (e.g. `f64`).
+impl<T> PartialEq for TypedIndex<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+impl<T> Eq for TypedIndex<T> {}
+impl<T> std::hash::Hash for TypedIndex<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
 impl<T> Copy for TypedIndex<T> {}
 impl<T> Clone for TypedIndex<T> {
     fn clone(&self) -> Self {
-        Self {
-            id: self.id,
-            ty: self.ty,
-        }
+        *self
     }
 }
 